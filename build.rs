@@ -1,5 +0,0 @@
-fn main() {
-    cc::Build::new()
-        .file("src/wrapper.c")
-        .compile("wrapper");
-}
@@ -0,0 +1,22 @@
+// Run with: cargo run --example lock_api --features lock-api
+
+use critirs::lock_api::CriticalMutex;
+use std::{sync::Arc, thread};
+
+fn main() {
+    let counter = Arc::new(CriticalMutex::new(0u32));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    *counter.lock() += 1;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    println!("count: {}", *counter.lock());
+}
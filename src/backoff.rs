@@ -0,0 +1,90 @@
+// This module should not use std.
+
+use crate::wrapper::yield_thread;
+
+use core::hint;
+
+/// A policy for waiting between attempts to enter a critical section, used by
+/// CriticalSection::enter_with_backoff().
+pub trait Backoff {
+    /// Waits before the next attempt.
+    fn snooze(&mut self);
+    /// Returns true once the policy has given up, and the caller should block instead.
+    fn is_completed(&self) -> bool;
+}
+
+/// Spins for exponentially more iterations after each attempt, then yields the thread
+/// instead, and completes after a fixed number of steps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExponentialBackoff {
+    step: u32,
+    spin_limit: u32,
+    yield_limit: u32,
+}
+
+impl ExponentialBackoff {
+    /// Creates an ExponentialBackoff which spins for up to 2^6 iterations, then yields until
+    /// it completes after 10 steps.
+    pub const fn new() -> Self {
+        Self::with_limits(6, 10)
+    }
+    /// Creates an ExponentialBackoff which spins for 2^step iterations while step is at most
+    /// spin_limit, then yields, and completes once step exceeds yield_limit. spin_limit is
+    /// capped at 16.
+    pub const fn with_limits(spin_limit: u32, yield_limit: u32) -> Self {
+        Self {
+            step: 0,
+            spin_limit: if spin_limit > 16 { 16 } else { spin_limit },
+            yield_limit,
+        }
+    }
+    /// Starts over from the first step.
+    pub fn reset(&mut self) {
+        self.step = 0
+    }
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backoff for ExponentialBackoff {
+    fn snooze(&mut self) {
+        if self.step <= self.spin_limit {
+            for _ in 0..1u32 << self.step {
+                hint::spin_loop();
+            }
+        } else {
+            yield_thread();
+        }
+        if self.step <= self.yield_limit {
+            self.step += 1;
+        }
+    }
+    fn is_completed(&self) -> bool {
+        self.step > self.yield_limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backoff, ExponentialBackoff};
+
+    #[test]
+    fn completes() {
+        let mut backoff = ExponentialBackoff::with_limits(2, 4);
+        for _ in 0..5 {
+            assert!(!backoff.is_completed());
+            backoff.snooze();
+        }
+        assert!(backoff.is_completed());
+        backoff.reset();
+        assert!(!backoff.is_completed());
+        assert_eq!(
+            ExponentialBackoff::new(),
+            ExponentialBackoff::with_limits(6, 10)
+        );
+    }
+}
@@ -1,76 +1,1024 @@
-use crate::wrapper::{leave_cs, set_cs_spin_count};
+#[cfg(any(
+    feature = "CriticalSection",
+    feature = "spin-fallback",
+    debug_assertions
+))]
+use crate::wrapper::current_thread_id;
+#[cfg(feature = "CriticalStatic")]
+use crate::wrapper::try_enter_cs;
+#[cfg(feature = "CriticalSection")]
+use crate::wrapper::{delete_cs, yield_thread};
+use crate::wrapper::{
+    enter_cs, leave_cs, owning_thread_id, set_cs_spin_count, CRITICAL_SECTION, CRIT_ZEROED,
+};
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+use crate::wrapper::{CRITICAL_SECTION_DYNAMIC_SPIN, CRITICAL_SECTION_NO_DEBUG_INFO};
 
 use static_assertions::assert_not_impl_all;
 
-use winapi::um::minwinbase::CRITICAL_SECTION;
+#[cfg(feature = "CriticalSection")]
+use core::hint;
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+use core::mem::ManuallyDrop;
+#[cfg(feature = "CriticalSection")]
+use core::ops::{Deref, DerefMut};
+#[cfg(any(not(feature = "no-poison"), feature = "spin-fallback"))]
+use core::sync::atomic::AtomicBool;
+#[cfg(feature = "spin-fallback")]
+use core::sync::atomic::AtomicU32;
+#[cfg(any(feature = "metrics", feature = "CriticalSection"))]
+use core::sync::atomic::AtomicU64;
+use core::{
+    cell::UnsafeCell,
+    cmp,
+    fmt::{self, Formatter},
+    hash::{Hash, Hasher},
+    ptr::{self, addr_of},
+    sync::atomic::{AtomicI32, AtomicUsize, Ordering},
+};
+
+// Windows keeps RTL_CRITICAL_SECTION_FLAG_* bits in the high byte of SpinCount.
+const SPIN_COUNT_MASK: usize = 0x00FF_FFFF;
 
-use core::cell::UnsafeCell;
+// Number of failed checks while waiting for a lock or turn before yielding instead of spinning
+#[cfg(any(
+    feature = "CriticalSection",
+    all(feature = "lock-api", feature = "std")
+))]
+pub(crate) const SPINS_BEFORE_YIELD: u32 = 64;
 
-pub(crate) const CRIT_ZEROED: CRITICAL_SECTION = CRITICAL_SECTION {
-    DebugInfo: 0 as *mut _,
-    LockCount: 0,
-    LockSemaphore: 0 as *mut _,
-    OwningThread: 0 as *mut _,
-    RecursionCount: 0,
-    SpinCount: 0,
-};
+/// A spin count for a critical section: the number of times a thread trying to enter spins
+/// before waiting on the kernel. Only meaningful on multiprocessor systems.
+///
+/// Windows keeps the spin count in the low 24 bits. The high bit once asked Windows to
+/// preallocate the wait event, and is reserved since Windows Vista, along with the other high
+/// bits which hold flags. Values are clamped to `SpinCount::MAX` so these are never set by
+/// accident.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpinCount(u32);
+
+impl SpinCount {
+    /// The largest spin count Windows can store.
+    pub const MAX: SpinCount = SpinCount(SPIN_COUNT_MASK as u32);
+
+    /// Creates a SpinCount, clamping it to `SpinCount::MAX`.
+    pub const fn new(spin_count: u32) -> Self {
+        if spin_count > Self::MAX.0 {
+            Self::MAX
+        } else {
+            Self(spin_count)
+        }
+    }
+    /// Returns the spin count as a u32.
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for SpinCount {
+    fn from(spin_count: u32) -> Self {
+        Self::new(spin_count)
+    }
+}
+
+impl From<SpinCount> for u32 {
+    fn from(spin_count: SpinCount) -> Self {
+        spin_count.get()
+    }
+}
+
+/// Settings for initializing a critical section, for when they come from configuration
+/// rather than code. With the serde feature this can be deserialized, and any missing fields
+/// take their default values.
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CriticalSectionConfig {
+    /// The spin count to initialize with, clamped as by SpinCount::new(). None means 0.
+    pub spin_count: Option<u32>,
+    /// See CriticalSectionBuilder::no_debug_info().
+    pub no_debug_info: bool,
+    /// See CriticalSectionBuilder::dynamic_spin().
+    pub dynamic_spin: bool,
+}
+
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+impl CriticalSectionConfig {
+    /// Creates a CriticalSection with these settings, returning the OS error code if it
+    /// cannot be initialized.
+    #[cfg(feature = "CriticalSection")]
+    pub fn build(&self) -> Result<crate::CriticalSection, u32> {
+        crate::CriticalSectionBuilder::from(*self).build()
+    }
+    pub(crate) fn spin_count(&self) -> SpinCount {
+        SpinCount::new(self.spin_count.unwrap_or(0))
+    }
+    // The flags for InitializeCriticalSectionEx.
+    pub(crate) fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.no_debug_info {
+            flags |= CRITICAL_SECTION_NO_DEBUG_INFO;
+        }
+        if self.dynamic_spin {
+            flags |= CRITICAL_SECTION_DYNAMIC_SPIN;
+        }
+        flags
+    }
+}
 
+// repr(C) keeps critical at offset zero, so a pointer to either is a pointer to both.
+#[repr(C)]
 pub(crate) struct PoisonableCriticalSection {
-    pub critical: UnsafeCell<CRITICAL_SECTION>,
-    poison: UnsafeCell<bool>,
+    pub(crate) critical: UnsafeCell<CRITICAL_SECTION>,
+    // Only written while entered, but may be read at any time.
+    #[cfg(not(feature = "no-poison"))]
+    poison: AtomicBool,
+    // Set only at the start of a SectionInner, which is always inside a CriticalSection's Arc.
+    #[cfg(feature = "CriticalSection")]
+    in_arc: bool,
+    // A label for diagnostics, set at construction.
+    pub(crate) name: Option<&'static str>,
+    // Assigned at construction for lock order tracking. Zero if untracked.
+    #[cfg(feature = "deadlock-detection")]
+    pub(crate) lock_id: u64,
+    #[cfg(feature = "metrics")]
+    counters: Counters,
+    // With spin-fallback, set once a CriticalStatic fails to initialize, after which the
+    // spin lock in fallback_owner is used instead of the CRITICAL_SECTION. It holds the id of
+    // the thread which holds the spin lock, or 0 while it is free.
+    #[cfg(feature = "spin-fallback")]
+    fallback: AtomicBool,
+    #[cfg(feature = "spin-fallback")]
+    fallback_owner: AtomicU32,
+}
+
+// The state only a CriticalSection uses, kept out of PoisonableCriticalSection so that
+// CriticalStatic and the other sections do not pay for it. repr(C) keeps base first, so a
+// PoisonableCriticalSection with in_arc set can be cast back to the SectionInner around it.
+#[cfg(feature = "CriticalSection")]
+#[repr(C)]
+pub(crate) struct SectionInner {
+    base: PoisonableCriticalSection,
+    // Set at construction by CriticalSection::new_non_reentrant().
+    pub(crate) non_reentrant: bool,
+    pub(crate) poison_policy: PoisonPolicy,
+    // Set at construction by CriticalSection::new_fair().
+    pub(crate) fair: bool,
+    // Set while the CRITICAL_SECTION is initialized. Dropping it then deletes the
+    // CRITICAL_SECTION, which the Arc does exactly once when the last strong reference goes,
+    // regardless of weak references.
+    pub(crate) needs_delete: bool,
+    tickets: Tickets,
+    // Counts deletes, for tests which check a section is deleted exactly once.
+    #[cfg(test)]
+    pub(crate) deletes: Option<&'static AtomicUsize>,
+}
+
+/// What happens when an EnteredCritical is dropped while its thread is panicking. Set with
+/// CriticalSectionBuilder::on_poison().
+#[cfg(feature = "CriticalSection")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PoisonPolicy {
+    /// Set the poison flag and leave, so other threads can observe it. This is the default.
+    #[default]
+    Flag,
+    /// Abort the process without leaving, so no other thread can observe the data the
+    /// critical section protects. Without the std feature, panics cannot be detected and
+    /// this behaves like Flag.
+    Abort,
+}
+
+// A thread takes the next ticket, and may enter once it is being served. The holder of the
+// ticket being served advances it after fully leaving.
+#[cfg(feature = "CriticalSection")]
+struct Tickets {
+    next: AtomicU64,
+    serving: AtomicU64,
+}
+
+#[cfg(feature = "metrics")]
+struct Counters {
+    enters: AtomicU64,
+    try_enter_failures: AtomicU64,
+    spin_fallbacks: AtomicU64,
+}
+
+/// A snapshot of the counters kept for a critical section with the metrics feature.
+/// Counters are updated with relaxed ordering, so a snapshot taken while other threads are
+/// using the critical section may be slightly out of date.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CriticalStats {
+    /// Number of times the critical section has been entered, including recursively.
+    pub enters: u64,
+    /// Number of times trying to enter failed because another thread held it.
+    pub try_enter_failures: u64,
+    /// Number of times try_enter_for() gave up spinning and yielded the thread.
+    pub spin_fallbacks: u64,
+}
+
+/// The state of a critical section observed as it was left, returned by
+/// EnteredCritical::leave_checked().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeaveOutcome {
+    /// Whether the critical section was poisoned just before it was left.
+    pub poisoned: bool,
+}
+
+/// A function called by set_poison_hook() whenever a critical section becomes poisoned.
+#[cfg(not(feature = "no-poison"))]
+pub type PoisonHook = fn(&EnteredCritical<'_>);
+
+// The address of the PoisonHook, or 0 if none is set.
+#[cfg(not(feature = "no-poison"))]
+static POISON_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets a function to call whenever a critical section of any kind goes from not poisoned
+/// to poisoned, and returns the previous one. None removes it. This is a plain function
+/// pointer rather than a closure, so it works without allocating, including for
+/// CriticalStatic.
+///
+/// The hook runs on the panicking thread while it still holds the critical section, just
+/// before leaving it, so the guard it is given can report the name, id and owning thread.
+/// It must not panic, since the thread is already panicking, and must not leave the
+/// critical section. Without the std feature, panics cannot be detected, so it is never
+/// called.
+#[cfg(not(feature = "no-poison"))]
+pub fn set_poison_hook(hook: Option<PoisonHook>) -> Option<PoisonHook> {
+    let old = POISON_HOOK.swap(hook.map_or(0, |hook| hook as usize), Ordering::AcqRel);
+    poison_hook_from(old)
+}
+
+#[cfg(not(feature = "no-poison"))]
+fn poison_hook_from(addr: usize) -> Option<PoisonHook> {
+    match addr {
+        0 => None,
+        // Safety: only set_poison_hook() stores a non-zero address, from a PoisonHook.
+        addr => Some(unsafe { core::mem::transmute::<usize, PoisonHook>(addr) }),
+    }
+}
+
+/// Storage for a critical section initialized by CriticalSection::in_place(), only ever
+/// handled as a MaybeUninit. It is opaque, and has no API of its own.
+#[cfg(all(feature = "CriticalSection", feature = "CriticalStatic"))]
+#[repr(transparent)]
+pub struct CriticalStorage(pub(crate) PoisonableCriticalSection);
+
+/// The identity of a critical section, only ever seen by reference. It compares, hashes and
+/// orders by address, consistently with CriticalSection and CriticalStaticRef, which both
+/// implement Borrow<SectionId>. A map keyed by either can be looked up through the
+/// SectionId of a guard or of the other form of handle.
+#[repr(transparent)]
+pub struct SectionId(PoisonableCriticalSection);
+
+impl SectionId {
+    pub(crate) fn of(inner: &PoisonableCriticalSection) -> &SectionId {
+        // Safety: SectionId is a repr(transparent) wrapper around PoisonableCriticalSection.
+        unsafe { &*(inner as *const PoisonableCriticalSection as *const SectionId) }
+    }
+}
+
+impl PartialEq for SectionId {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+impl Eq for SectionId {}
+impl Hash for SectionId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self as *const SectionId as usize).hash(state)
+    }
+}
+impl PartialOrd for SectionId {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for SectionId {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (self as *const SectionId as usize).cmp(&(other as *const SectionId as usize))
+    }
+}
+
+impl fmt::Debug for SectionId {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "SectionId: {:p}", self)?;
+        self.0.write_name(out)
+    }
+}
+
+/// A critical section which can be entered through a shared reference, for code generic over
+/// CriticalSection and CriticalStaticRef<Init>. Guards are returned whether or not the
+/// critical section is poisoned, as with CriticalSection::enter_unchecked().
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+pub trait Lockable {
+    /// The guard returned on entering, which leaves when dropped.
+    type Guard<'a>
+    where
+        Self: 'a;
+    /// Enters, blocking until available.
+    fn enter(&self) -> Self::Guard<'_>;
+    /// Tries to enter without blocking.
+    fn try_enter(&self) -> Option<Self::Guard<'_>>;
 }
 
 // impl PoisonableCriticalSection {
 //     pub(crate) unsafe fn clear_poison_unsynced(&self) {
-//         self.poison.get().write(false)
+//         self.poison.store(false, Ordering::Relaxed)
 //     }
 // }
 
+impl PoisonableCriticalSection {
+    // The fallback spin lock is not recursive, so its holder has always entered it once.
+    pub(crate) fn recursion_count(&self) -> i32 {
+        #[cfg(feature = "spin-fallback")]
+        if self.in_fallback() {
+            return if self.is_locked() { 1 } else { 0 };
+        }
+        // Safety: RecursionCount is only written by the owning thread, so this
+        // is only meaningful while the calling thread is entered.
+        unsafe { addr_of!((*self.critical.get()).RecursionCount).read_volatile() }
+    }
+    pub(crate) fn owning_thread_id(&self) -> Option<u32> {
+        #[cfg(feature = "spin-fallback")]
+        if self.in_fallback() {
+            return match self.fallback_owner.load(Ordering::Relaxed) {
+                0 => None,
+                id => Some(id),
+            };
+        }
+        // Safety: critical is valid for as long as self is.
+        unsafe { owning_thread_id(self.critical.get()) }
+    }
+    pub(crate) fn lock_count(&self) -> i32 {
+        // Safety: LockCount is modified with interlocked operations by other threads,
+        // so read it atomically. The value may be stale once read.
+        unsafe {
+            let ptr = addr_of!((*self.critical.get()).LockCount) as *const AtomicI32;
+            (*ptr).load(Ordering::Relaxed)
+        }
+    }
+    // An initialized critical section has DebugInfo set, or -1 for no debug info, and a
+    // LockCount of -1 while free. Being locked is the only way for LockCount to be 0, and
+    // that also sets the owner, so all three being zero means it was never initialized.
+    #[cfg(feature = "CriticalStatic")]
+    pub(crate) fn looks_zeroed(&self) -> bool {
+        // Safety: only reads. DebugInfo does not change after init.
+        let debug_info = unsafe { addr_of!((*self.critical.get()).DebugInfo).read_volatile() };
+        debug_info.is_null() && self.lock_count() == 0 && self.owning_thread_id().is_none()
+    }
+    pub(crate) fn spin_count(&self) -> u32 {
+        // Safety: SpinCount may be set by any thread, so read it atomically.
+        let spin_count = unsafe {
+            let ptr = addr_of!((*self.critical.get()).SpinCount) as *const AtomicUsize;
+            (*ptr).load(Ordering::Relaxed)
+        };
+        (spin_count & SPIN_COUNT_MASK) as u32
+    }
+    // With the no-poison feature, there is no flag, and nothing is ever poisoned.
+    pub(crate) fn is_poisoned(&self) -> bool {
+        #[cfg(not(feature = "no-poison"))]
+        return self.poison.load(Ordering::Relaxed);
+        #[cfg(feature = "no-poison")]
+        false
+    }
+    // Only call while entered.
+    fn set_poison(&self, _poison: bool) {
+        #[cfg(not(feature = "no-poison"))]
+        self.poison.store(_poison, Ordering::Relaxed)
+    }
+    // Only call while entered.
+    fn take_poison(&self) -> bool {
+        #[cfg(not(feature = "no-poison"))]
+        return self.poison.swap(false, Ordering::Relaxed);
+        #[cfg(feature = "no-poison")]
+        false
+    }
+    // Exclusive access means no thread can be entered, so no need to enter first.
+    #[cfg(feature = "CriticalSection")]
+    pub(crate) fn clear_poison_mut(&mut self) {
+        #[cfg(not(feature = "no-poison"))]
+        {
+            *self.poison.get_mut() = false
+        }
+    }
+    // Since Windows Vista, the bits above the lowest two of LockCount hold the negated number
+    // of threads waiting.
+    #[cfg(feature = "CriticalSection")]
+    pub(crate) fn approx_waiter_count(&self) -> u32 {
+        ((-1 - self.lock_count()) >> 2) as u32
+    }
+    pub(crate) fn is_locked(&self) -> bool {
+        #[cfg(feature = "spin-fallback")]
+        if self.in_fallback() {
+            return self.fallback_owner.load(Ordering::Relaxed) != 0;
+        }
+        // Since Windows Vista, the lowest bit of LockCount is clear while the section is held.
+        self.lock_count() & 1 == 0
+    }
+    #[cfg(feature = "CriticalSection")]
+    pub(crate) fn held_by_current_thread(&self) -> bool {
+        self.owning_thread_id() == Some(current_thread_id()) && self.is_locked()
+    }
+    // A cheap pre-check before trying to enter. A stale answer only costs a wasted or extra
+    // spin, since the real attempt decides.
+    #[cfg(feature = "CriticalSection")]
+    pub(crate) fn held_by_other_thread(&self) -> bool {
+        matches!(self.owning_thread_id(), Some(id) if id != current_thread_id())
+    }
+    // Only a CriticalSection has a SectionInner around its PoisonableCriticalSection.
+    #[cfg(feature = "CriticalSection")]
+    pub(crate) fn section_inner(&self) -> Option<&SectionInner> {
+        if self.in_arc {
+            // Safety: in_arc is only set on the base of a SectionInner, which is repr(C).
+            Some(unsafe { &*(self as *const Self as *const SectionInner) })
+        } else {
+            None
+        }
+    }
+    #[cfg(feature = "CriticalSection")]
+    pub(crate) fn is_fair(&self) -> bool {
+        self.section_inner().is_some_and(|inner| inner.fair)
+    }
+    #[cfg(all(feature = "CriticalSection", feature = "std"))]
+    fn poison_policy(&self) -> PoisonPolicy {
+        self.section_inner()
+            .map_or(PoisonPolicy::Flag, |inner| inner.poison_policy)
+    }
+    // The *_turn functions do nothing unless the section is fair. A thread which already owns
+    // the section never takes a turn, so recursive entry cannot deadlock.
+    #[cfg(feature = "CriticalSection")]
+    fn turn_tickets(&self) -> Option<&Tickets> {
+        let inner = self.section_inner().filter(|inner| inner.fair)?;
+        if self.owning_thread_id() == Some(current_thread_id()) {
+            return None;
+        }
+        Some(&inner.tickets)
+    }
+    pub(crate) fn wait_turn(&self) {
+        #[cfg(feature = "CriticalSection")]
+        if let Some(tickets) = self.turn_tickets() {
+            let ticket = tickets.next.fetch_add(1, Ordering::Relaxed);
+            let mut spins = 0;
+            while tickets.serving.load(Ordering::Acquire) != ticket {
+                if spins < SPINS_BEFORE_YIELD {
+                    spins += 1;
+                    hint::spin_loop();
+                } else {
+                    yield_thread();
+                }
+            }
+        }
+    }
+    // Only takes a turn if no other thread holds or is waiting for one.
+    #[cfg(feature = "CriticalSection")]
+    pub(crate) fn try_take_turn(&self) -> bool {
+        let tickets = match self.turn_tickets() {
+            Some(tickets) => tickets,
+            None => return true,
+        };
+        let serving = tickets.serving.load(Ordering::Acquire);
+        tickets
+            .next
+            .compare_exchange(serving, serving + 1, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+    #[cfg(any(feature = "CriticalSection", feature = "CriticalCondvar"))]
+    pub(crate) fn end_turn(&self) {
+        #[cfg(feature = "CriticalSection")]
+        if let Some(inner) = self.section_inner().filter(|inner| inner.fair) {
+            inner.tickets.serving.fetch_add(1, Ordering::Release);
+        }
+    }
+    // Without the spin-fallback feature, this is always false. Only a CriticalStatic ever
+    // switches to the fallback, and only during its initialization.
+    pub(crate) fn in_fallback(&self) -> bool {
+        #[cfg(feature = "spin-fallback")]
+        return self.fallback.load(Ordering::Relaxed);
+        #[cfg(not(feature = "spin-fallback"))]
+        false
+    }
+    // Only call while no thread can be using the section, such as during initialization.
+    #[cfg(feature = "spin-fallback")]
+    pub(crate) fn set_fallback(&self, fallback: bool) {
+        self.fallback.store(fallback, Ordering::Relaxed)
+    }
+    // Enters without a guard, through the fallback spin lock if it is in use.
+    pub(crate) unsafe fn enter_raw(&self) {
+        #[cfg(feature = "spin-fallback")]
+        if self.in_fallback() {
+            while !self.try_enter_raw() {
+                while self.fallback_owner.load(Ordering::Relaxed) != 0 {
+                    core::hint::spin_loop();
+                }
+            }
+            return;
+        }
+        enter_cs(self.critical.get())
+    }
+    // Returns whether it was entered, through the fallback spin lock if it is in use.
+    #[cfg(feature = "CriticalStatic")]
+    pub(crate) unsafe fn try_enter_raw(&self) -> bool {
+        #[cfg(feature = "spin-fallback")]
+        if self.in_fallback() {
+            return self
+                .fallback_owner
+                .compare_exchange_weak(0, current_thread_id(), Ordering::Acquire, Ordering::Relaxed)
+                .is_ok();
+        }
+        try_enter_cs(self.critical.get()) != 0
+    }
+    // The fallback spin lock has no spin count, so this does nothing and returns 0 for it.
+    pub(crate) fn set_spin_count(&self, spin_count: SpinCount) -> u32 {
+        if self.in_fallback() {
+            return 0;
+        }
+        // Safety: cannot fail. Returns previous spin_count. Naturally thread-safe.
+        unsafe { set_cs_spin_count(self.critical.get(), spin_count.get()) }
+    }
+    // Leaves once, ending this thread's turn if that fully released the section.
+    pub(crate) unsafe fn leave(&self) {
+        #[cfg(feature = "spin-fallback")]
+        if self.in_fallback() {
+            self.fallback_owner.store(0, Ordering::Release);
+            return;
+        }
+        #[cfg(feature = "CriticalSection")]
+        if self.is_fair() {
+            let outermost = self.recursion_count() == 1;
+            leave_cs(self.critical.get());
+            if outermost {
+                self.end_turn();
+            }
+            return;
+        }
+        leave_cs(self.critical.get())
+    }
+    // Writes the name for Debug output, if there is one.
+    pub(crate) fn write_name(&self, out: &mut Formatter) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(out, " {:?}", name),
+            None => Ok(()),
+        }
+    }
+    // The record_* functions bump the metrics counters and emit tracing events, and compile to
+    // nothing without either feature. The wrapper's tracing events only have the pointer, so
+    // named sections add their name.
+    pub(crate) fn record_enter(&self) {
+        #[cfg(feature = "metrics")]
+        self.counters.enters.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "tracing")]
+        if let Some(name) = self.name {
+            tracing::trace!(critical = ?self.critical.get(), name, "entered named critical section");
+        }
+    }
+    pub(crate) fn record_try_enter_failure(&self) {
+        #[cfg(feature = "metrics")]
+        self.counters
+            .try_enter_failures
+            .fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "tracing")]
+        if let Some(name) = self.name {
+            tracing::debug!(critical = ?self.critical.get(), name, "named critical section contended");
+        }
+    }
+    #[cfg(all(feature = "std", feature = "CriticalSection"))]
+    pub(crate) fn record_spin_fallback(&self) {
+        #[cfg(feature = "metrics")]
+        self.counters.spin_fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+    #[cfg(feature = "metrics")]
+    pub(crate) fn stats(&self) -> CriticalStats {
+        CriticalStats {
+            enters: self.counters.enters.load(Ordering::Relaxed),
+            try_enter_failures: self.counters.try_enter_failures.load(Ordering::Relaxed),
+            spin_fallbacks: self.counters.spin_fallbacks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(feature = "CriticalSection")]
+impl SectionInner {
+    pub(crate) fn new() -> Self {
+        let mut base = POISONABLE_ZEROED;
+        base.in_arc = true;
+        Self {
+            base,
+            non_reentrant: false,
+            poison_policy: PoisonPolicy::Flag,
+            fair: false,
+            needs_delete: false,
+            tickets: Tickets {
+                next: AtomicU64::new(0),
+                serving: AtomicU64::new(0),
+            },
+            #[cfg(test)]
+            deletes: None,
+        }
+    }
+}
+
+#[cfg(feature = "CriticalSection")]
+impl Deref for SectionInner {
+    type Target = PoisonableCriticalSection;
+    fn deref(&self) -> &PoisonableCriticalSection {
+        &self.base
+    }
+}
+
+#[cfg(feature = "CriticalSection")]
+impl DerefMut for SectionInner {
+    fn deref_mut(&mut self) -> &mut PoisonableCriticalSection {
+        &mut self.base
+    }
+}
+
+#[cfg(feature = "CriticalSection")]
+impl Drop for SectionInner {
+    fn drop(&mut self) {
+        if self.needs_delete {
+            // Safety: we have exclusive access in drop, needs_delete is only set while
+            // initialized, and FFI call never fails
+            unsafe { delete_cs(self.critical.get_mut()) }
+            #[cfg(test)]
+            if let Some(deletes) = self.deletes {
+                deletes.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// Only ever copied into new sections, never borrowed in place.
+#[allow(clippy::declare_interior_mutable_const)]
 pub(crate) const POISONABLE_ZEROED: PoisonableCriticalSection = PoisonableCriticalSection {
     critical: UnsafeCell::new(CRIT_ZEROED),
-    poison: UnsafeCell::new(false),
+    #[cfg(not(feature = "no-poison"))]
+    poison: AtomicBool::new(false),
+    #[cfg(feature = "CriticalSection")]
+    in_arc: false,
+    name: None,
+    #[cfg(feature = "deadlock-detection")]
+    lock_id: 0,
+    #[cfg(feature = "metrics")]
+    counters: Counters {
+        enters: AtomicU64::new(0),
+        try_enter_failures: AtomicU64::new(0),
+        spin_fallbacks: AtomicU64::new(0),
+    },
+    #[cfg(feature = "spin-fallback")]
+    fallback: AtomicBool::new(false),
+    #[cfg(feature = "spin-fallback")]
+    fallback_owner: AtomicU32::new(0),
 };
 
-pub struct EnteredCritical<'c>(&'c PoisonableCriticalSection);
+// The bool is set for guards from reborrow(), which do not leave when dropped.
+pub struct EnteredCritical<'c>(&'c PoisonableCriticalSection, EnteringThread, bool);
+
+// The id of the thread which entered, checked when leaving. Zero-sized unless the
+// debug-thread-check feature is enabled in a debug build.
+struct EnteringThread {
+    #[cfg(all(feature = "debug-thread-check", debug_assertions))]
+    id: u32,
+}
+
+impl EnteringThread {
+    fn current() -> Self {
+        Self {
+            #[cfg(all(feature = "debug-thread-check", debug_assertions))]
+            id: current_thread_id(),
+        }
+    }
+    // Panics without leaving, since leaving from another thread is undefined behavior.
+    fn check(&self) {
+        #[cfg(all(feature = "debug-thread-check", debug_assertions))]
+        if self.id != current_thread_id() {
+            panic!(
+                "EnteredCritical entered by thread {} is being left by thread {}",
+                self.id,
+                current_thread_id()
+            )
+        }
+    }
+}
 
 // Safety: it is not okay to enter from one thread and leave from another, or leave twice.
 assert_not_impl_all!(EnteredCritical: Send, Sync, Copy, Clone);
 
 impl<'c> EnteredCritical<'c> {
     pub(crate) unsafe fn new(ptr: &'c PoisonableCriticalSection) -> Self {
-        Self(ptr)
+        ptr.record_enter();
+        Self(ptr, EnteringThread::current(), false)
     }
 }
 
-impl EnteredCritical<'_> {
+impl<'c> EnteredCritical<'c> {
     #[allow(non_snake_case)]
-    fn lpCriticalSection(&self) -> *mut CRITICAL_SECTION {
+    pub(crate) fn lpCriticalSection(&self) -> *mut CRITICAL_SECTION {
         self.0.critical.get()
     }
     pub fn leave(self) {
         drop(self)
     }
-    pub fn set_spin_count(&self, spin_count: u32) -> u32 {
-        // Safety: cannot fail. Returns previous spin_count. Naturally thread-safe.
-        unsafe { set_cs_spin_count(self.lpCriticalSection(), spin_count) }
+    /// Returns a guard for the same critical section which borrows this one, for passing to
+    /// functions which take an EnteredCritical by value as proof the lock is held. Dropping
+    /// or leaving the returned guard does not leave the critical section, and this guard
+    /// cannot be used or dropped until the returned one is gone, so the critical section is
+    /// still held for as long as either exists. Only this original guard leaves it.
+    ///
+    /// The returned guard is otherwise a normal EnteredCritical. Calling bump() on it leaves
+    /// and re-enters as usual, and if its thread panics, this guard still poisons the
+    /// critical section when it is dropped during unwinding.
+    pub fn reborrow(&mut self) -> EnteredCritical<'_> {
+        EnteredCritical(self.0, EnteringThread::current(), true)
+    }
+    /// Leaves the critical section like leave(), returning whether it was poisoned at that
+    /// point. The poison flag is read while still entered, so no other thread can change it
+    /// in between.
+    #[must_use]
+    pub fn leave_checked(self) -> LeaveOutcome {
+        let poisoned = self.is_poisoned();
+        drop(self);
+        LeaveOutcome { poisoned }
+    }
+    #[cfg(feature = "CriticalCondvar")]
+    pub(crate) fn in_fallback(&self) -> bool {
+        self.0.in_fallback()
+    }
+    // SleepConditionVariableCS leaves and re-enters without taking a turn. Call end_turn()
+    // before sleeping and requeue() after, while entered exactly once.
+    #[cfg(feature = "CriticalCondvar")]
+    pub(crate) fn end_turn(&self) {
+        self.0.end_turn()
+    }
+    #[cfg(feature = "CriticalCondvar")]
+    pub(crate) unsafe fn requeue(&self) {
+        #[cfg(feature = "CriticalSection")]
+        if self.0.is_fair() {
+            leave_cs(self.lpCriticalSection());
+            self.0.wait_turn();
+            enter_cs(self.lpCriticalSection());
+        }
+    }
+    /// Turns this guard into a RawHeldToken, which keeps the critical section held without
+    /// leaving it when dropped. Like the guard, the token cannot leave the calling thread,
+    /// so the critical section is always released by the thread which entered it.
+    #[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+    pub fn forget_guard(self) -> RawHeldToken<'c> {
+        RawHeldToken(ManuallyDrop::new(self))
+    }
+    /// Wraps this guard in a SendEnteredCritical, which may be moved between threads.
+    ///
+    /// # Safety
+    ///
+    /// Windows requires that a critical section be left by the same thread which entered
+    /// it. The caller must ensure the returned guard is left or dropped on the thread which
+    /// called this function, for example by sending it back before releasing it. Leaving from
+    /// any other thread leaves the critical section in an undefined state, and may cause
+    /// other threads waiting to enter it to deadlock.
+    pub unsafe fn into_send(self) -> SendEnteredCritical<'c> {
+        SendEnteredCritical(self)
+    }
+    /// Leaves and immediately re-enters the critical section, giving waiting threads a
+    /// chance to enter it. Another thread may have run in between, so any invariants must
+    /// be re-checked afterwards. The poison state is unchanged.
+    ///
+    /// If the calling thread has entered the critical section more than once, it is not
+    /// actually released.
+    pub fn bump(&mut self) {
+        // Safety: we are entered, so leave once and enter again before returning.
+        // Neither call can fail. Naturally thread-safe.
+        unsafe {
+            self.0.leave();
+            self.0.wait_turn();
+            self.0.enter_raw();
+        }
+    }
+    pub fn set_spin_count(&self, spin_count: impl Into<SpinCount>) -> u32 {
+        self.0.set_spin_count(spin_count.into())
+    }
+    /// Returns the current spin count of this Critical Section.
+    pub fn spin_count(&self) -> u32 {
+        self.0.spin_count()
+    }
+    /// Sets the spin count to the result of the provided function applied to the current
+    /// one, and returns the new value, clamped as by SpinCount::new().
+    pub fn adjust_spin_count(&self, f: impl FnOnce(u32) -> u32) -> u32 {
+        let spin_count = SpinCount::new(f(self.spin_count()));
+        self.set_spin_count(spin_count);
+        spin_count.get()
     }
     pub fn is_poisoned(&self) -> bool {
-        // Safety: can only read or write poison value while entered
-        unsafe { self.0.poison.get().read() }
+        self.0.is_poisoned()
     }
     pub fn clear_poison(&self) {
-        // Safety: can only read or write poison value while entered
-        unsafe { self.0.poison.get().write(false) }
+        // Only write poison value while entered
+        self.0.set_poison(false)
+    }
+    /// Clears the poison flag, returning whether it was set, for recovery code which needs
+    /// to know while resetting it.
+    pub fn take_poison(&self) -> bool {
+        // Only write poison value while entered
+        self.0.take_poison()
+    }
+    /// Returns the number of times the current thread has entered this critical section
+    /// without leaving. The count is read from the underlying CRITICAL_SECTION, which is
+    /// only well-defined while the calling thread holds it, as it does here.
+    pub fn recursion_count(&self) -> i32 {
+        self.0.recursion_count()
+    }
+    /// Returns a new handle to the CriticalSection this guard entered, as if it were cloned,
+    /// so it can be passed on without threading the original reference through. Returns
+    /// None if the guard is not from a CriticalSection, such as one from a CriticalStatic.
+    ///
+    /// The handle keeps the critical section alive by itself, and does not extend the
+    /// guard's lifetime, which is still tied to wherever it was entered from.
+    #[cfg(feature = "CriticalSection")]
+    pub fn section(&self) -> Option<crate::CriticalSection> {
+        // Safety: only a CriticalSection has a SectionInner, and it keeps the Arc alive for
+        // at least as long as this guard borrows it.
+        self.0
+            .section_inner()
+            .map(|inner| unsafe { crate::CriticalSection::from_inner(inner) })
+    }
+    /// Runs the provided closure with a pointer to the underlying CRITICAL_SECTION, for
+    /// passing to other Win32 APIs, such as SleepConditionVariableCS. The critical section
+    /// is held for the whole call.
+    ///
+    /// The closure must not call DeleteCriticalSection on the pointer, and must leave the
+    /// critical section entered exactly as many times as it was when the closure was called.
+    /// In particular, it must not call LeaveCriticalSection without entering again first.
+    ///
+    /// With the spin-fallback feature, a CriticalStatic which failed to initialize is held
+    /// through a spin lock instead, and the pointer is to a CRITICAL_SECTION which was never
+    /// initialized and must not be used.
+    pub fn with_raw<R>(&self, f: impl FnOnce(*mut CRITICAL_SECTION) -> R) -> R {
+        f(self.lpCriticalSection())
+    }
+    /// Returns the identity of the critical section this guard entered.
+    pub fn id(&self) -> &SectionId {
+        SectionId::of(self.0)
+    }
+    /// Returns the name the critical section was given, if any.
+    pub fn name(&self) -> Option<&'static str> {
+        self.0.name
+    }
+    /// Returns the id of the thread which owns this critical section. While entered, this
+    /// is always the current thread.
+    pub fn owning_thread_id(&self) -> Option<u32> {
+        self.0.owning_thread_id()
+    }
+    /// In debug builds, panics unless the calling thread owns the critical section and has
+    /// entered it at least once, which always holds for a guard made safely. This catches a
+    /// guard which unsafe code has broken, such as by leaving its critical section through
+    /// with_raw(), or by returning a SendEnteredCritical to the wrong thread. In release
+    /// builds, this does nothing.
+    pub fn assert_held(&self) {
+        #[cfg(debug_assertions)]
+        {
+            let owner = self.owning_thread_id();
+            if owner != Some(current_thread_id()) {
+                panic!(
+                    "EnteredCritical is not held by the current thread, owner is {:?}",
+                    owner
+                )
+            }
+            let recursion_count = self.recursion_count();
+            if recursion_count <= 0 {
+                panic!(
+                    "EnteredCritical held with recursion count {}",
+                    recursion_count
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Debug for EnteredCritical<'_> {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "EnteredCritical: {:p}", self.0)?;
+        self.0.write_name(out)?;
+        write!(
+            out,
+            " (recursion count: {}, poisoned: {})",
+            self.recursion_count(),
+            self.is_poisoned()
+        )
     }
 }
 
 impl Drop for EnteredCritical<'_> {
     fn drop(&mut self) {
+        if self.2 {
+            // Borrowed from another guard, which will leave.
+            return;
+        }
+        self.1.check();
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
         if std::thread::panicking() {
-            // Safety: can only read or write poison value while entered
-            unsafe { self.0.poison.get().write(true) }
+            #[cfg(feature = "CriticalSection")]
+            if self.0.poison_policy() == PoisonPolicy::Abort {
+                std::process::abort()
+            }
+            // Only write poison value while entered
+            if !self.0.poison.swap(true, Ordering::Relaxed) {
+                if let Some(hook) = poison_hook_from(POISON_HOOK.load(Ordering::Acquire)) {
+                    hook(self)
+                }
+            }
+        }
+        // Without poisoning, only check for a panic when it would abort.
+        #[cfg(all(feature = "std", feature = "no-poison", feature = "CriticalSection"))]
+        if self.0.poison_policy() == PoisonPolicy::Abort && std::thread::panicking() {
+            std::process::abort()
         }
+        #[cfg(feature = "deadlock-detection")]
+        crate::lock_order::left(self.0.lock_id);
         // Safety: Cannot fail, no return value, leave exactly once.
-        unsafe { leave_cs(self.lpCriticalSection()) }
+        unsafe { self.0.leave() }
+    }
+}
+
+/// An EnteredCritical which may be sent to other threads, obtained from the unsafe
+/// EnteredCritical::into_send(). It must still be left on the thread which entered.
+pub struct SendEnteredCritical<'c>(EnteredCritical<'c>);
+
+// Safety: into_send() requires the caller to return the guard to the entering thread
+// before it is left or dropped.
+unsafe impl Send for SendEnteredCritical<'_> {}
+assert_not_impl_all!(SendEnteredCritical: Sync, Copy, Clone);
+
+impl SendEnteredCritical<'_> {
+    /// Leaves the critical section. This must be called on the thread which entered it.
+    pub fn leave(self) {
+        self.0.leave()
+    }
+}
+
+/// A critical section held without a guard, obtained from EnteredCritical::forget_guard(),
+/// for control flow which RAII scoping does not fit, such as a Win32 callback which runs
+/// later on the same thread. It is released by calling leave() on the same thread.
+///
+/// Dropping the token does not leave the critical section. It stays held by this thread
+/// until the thread exits, and every other thread which tries to enter it blocks forever.
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+#[must_use = "dropping a RawHeldToken leaves the critical section held forever"]
+#[derive(Debug)]
+pub struct RawHeldToken<'c>(ManuallyDrop<EnteredCritical<'c>>);
+
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+assert_not_impl_all!(RawHeldToken: Send, Sync, Copy, Clone);
+
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+impl RawHeldToken<'_> {
+    /// Leaves the critical section. Like dropping the guard, this poisons it if the thread
+    /// is panicking.
+    pub fn leave(self) {
+        ManuallyDrop::into_inner(self.0).leave()
+    }
+}
+
+/// A stack of EnteredCritical guards which are left in the reverse of the order they were
+/// pushed, regardless of how the variables holding them would otherwise be dropped. Useful
+/// when entering a dynamic number of critical sections, such as in a loop.
+#[cfg(feature = "CriticalSection")]
+#[derive(Debug, Default)]
+pub struct NestedGuards<'c>(alloc::vec::Vec<EnteredCritical<'c>>);
+
+#[cfg(feature = "CriticalSection")]
+impl<'c> NestedGuards<'c> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(alloc::vec::Vec::with_capacity(capacity))
+    }
+    /// Pushes a guard, which will be left before any guard pushed earlier.
+    pub fn push(&mut self, guard: EnteredCritical<'c>) {
+        self.0.push(guard)
+    }
+    /// Removes the most recently pushed guard, leaving it to the caller.
+    pub fn pop(&mut self) -> Option<EnteredCritical<'c>> {
+        self.0.pop()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Leaves every guard, most recently pushed first.
+    pub fn release(self) {
+        drop(self)
+    }
+}
+
+#[cfg(feature = "CriticalSection")]
+impl Drop for NestedGuards<'_> {
+    fn drop(&mut self) {
+        // Vec drops its elements front to back, so pop them instead.
+        while let Some(guard) = self.0.pop() {
+            drop(guard)
+        }
     }
 }
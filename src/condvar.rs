@@ -0,0 +1,225 @@
+// This module should not use std, so that it may be paired with CriticalStatic.
+
+use crate::EnteredCritical;
+
+use crate::wrapper::{
+    sleep_cv_cs, wake_all_cv, wake_cv, CONDITION_VARIABLE, CONDITION_VARIABLE_INIT, DWORD,
+    ERROR_TIMEOUT, INFINITE,
+};
+
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Formatter},
+    time::Duration,
+};
+
+/// CriticalCondvar is a condition variable which may be waited on while holding any
+/// critical section provided by this crate.
+///
+/// Like any condition variable, waiting may wake spuriously, so callers should re-check
+/// their condition in a loop.
+pub struct CriticalCondvar {
+    inner: UnsafeCell<CONDITION_VARIABLE>,
+}
+
+// Safety: Condition Variable API is naturally thread-safe. A CONDITION_VARIABLE may be
+// moved so long as no thread is waiting on it, which the borrow checker guarantees.
+unsafe impl Send for CriticalCondvar {}
+unsafe impl Sync for CriticalCondvar {}
+
+impl CriticalCondvar {
+    /// Creates a new CriticalCondvar.
+    pub const fn new() -> Self {
+        Self {
+            inner: UnsafeCell::new(CONDITION_VARIABLE_INIT),
+        }
+    }
+    /// Atomically leaves the entered critical section and blocks until notified, then
+    /// re-enters the critical section before returning.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section has been entered more than once by the calling thread,
+    /// since it would not actually be released while waiting.
+    pub fn wait<'c>(&self, entered: EnteredCritical<'c>) -> EnteredCritical<'c> {
+        self.sleep(&entered, INFINITE)
+            .expect("Failed to wait on condition variable");
+        entered
+    }
+    /// Like wait(), but gives up after roughly the provided duration. Returns the re-entered
+    /// critical section, and true if the wait timed out.
+    ///
+    /// Durations are rounded up to the next millisecond, so a zero or sub-millisecond duration
+    /// still waits for 1ms. Durations too long to represent in milliseconds are clamped to the
+    /// longest finite wait.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section has been entered more than once by the calling thread,
+    /// since it would not actually be released while waiting.
+    pub fn wait_timeout<'c>(
+        &self,
+        entered: EnteredCritical<'c>,
+        dur: Duration,
+    ) -> (EnteredCritical<'c>, bool) {
+        match self.sleep(&entered, duration_to_millis(dur)) {
+            Ok(()) => (entered, false),
+            Err(ERROR_TIMEOUT) => (entered, true),
+            Err(_) => panic!("Failed to wait on condition variable"),
+        }
+    }
+    /// Wakes one thread waiting on this condition variable.
+    pub fn notify_one(&self) {
+        // Safety: cannot fail, no return value. Naturally thread-safe.
+        unsafe { wake_cv(self.inner.get()) }
+    }
+    /// Wakes all threads waiting on this condition variable.
+    pub fn notify_all(&self) {
+        // Safety: cannot fail, no return value. Naturally thread-safe.
+        unsafe { wake_all_cv(self.inner.get()) }
+    }
+    fn sleep(&self, entered: &EnteredCritical, millis: DWORD) -> Result<(), DWORD> {
+        if entered.in_fallback() {
+            panic!(
+                "Cannot wait on a condition variable with a CriticalStatic in spin-fallback mode"
+            )
+        }
+        if entered.recursion_count() != 1 {
+            panic!("Critical section must be entered exactly once to wait on a condition variable")
+        }
+        // Safety: we are entered exactly once on this thread, and the section is entered
+        // again when the call returns, whether or not it succeeded.
+        unsafe {
+            entered.end_turn();
+            let result = sleep_cv_cs(self.inner.get(), entered.lpCriticalSection(), millis);
+            entered.requeue();
+            result
+        }
+    }
+}
+
+fn duration_to_millis(dur: Duration) -> DWORD {
+    let mut millis = dur.as_millis();
+    if dur.as_nanos() > millis * 1_000_000 {
+        millis += 1;
+    }
+    // INFINITE is u32::MAX, so clamp just below it
+    millis.max(1).min(INFINITE as u128 - 1) as DWORD
+}
+
+impl Default for CriticalCondvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CriticalCondvar {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "CriticalCondvar: {:p}", self.inner.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::duration_to_millis;
+    use crate::{CriticalCondvar, CriticalSection, CriticalStatic};
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn bounded_queue() {
+        const CAPACITY: usize = 4;
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        static NOT_EMPTY: CriticalCondvar = CriticalCondvar::new();
+        static NOT_FULL: CriticalCondvar = CriticalCondvar::new();
+        static mut LEN: usize = 0;
+        static mut CONSUMED: usize = 0;
+
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..25 {
+                        let mut entered = CRITICAL.enter();
+                        while unsafe { LEN } == 0 {
+                            entered = NOT_EMPTY.wait(entered);
+                        }
+                        unsafe {
+                            LEN -= 1;
+                            CONSUMED += 1;
+                        }
+                        NOT_FULL.notify_one();
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..100 {
+            let mut entered = CRITICAL.enter();
+            while unsafe { LEN } == CAPACITY {
+                entered = NOT_FULL.wait(entered);
+            }
+            unsafe { LEN += 1 }
+            NOT_EMPTY.notify_one();
+        }
+        for consumer in consumers {
+            consumer.join().unwrap();
+        }
+        assert_eq!(0, unsafe { LEN });
+        assert_eq!(100, unsafe { CONSUMED });
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let critical = CriticalSection::new();
+        let condvar = CriticalCondvar::new();
+        let start = Instant::now();
+        let (entered, timed_out) =
+            condvar.wait_timeout(critical.enter_unchecked(), Duration::from_millis(100));
+        assert!(timed_out);
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert_eq!(1, entered.recursion_count());
+    }
+
+    #[test]
+    fn fair_wait() {
+        let critical = CriticalSection::new_fair();
+        let condvar = Arc::new(CriticalCondvar::new());
+        let ready = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let (critical, condvar, ready) = (critical.clone(), condvar.clone(), ready.clone());
+            thread::spawn(move || {
+                let mut entered = critical.enter_unchecked();
+                while !ready.load(Ordering::Relaxed) {
+                    entered = condvar.wait(entered);
+                }
+            })
+        };
+        for _ in 0..10 {
+            let _entered = critical.enter_unchecked();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let entered = critical.enter_unchecked();
+        ready.store(true, Ordering::Relaxed);
+        condvar.notify_all();
+        entered.leave();
+        waiter.join().unwrap();
+        assert!(critical.try_enter().is_some());
+    }
+
+    #[test]
+    fn timeout_millis() {
+        assert_eq!(1, duration_to_millis(Duration::from_secs(0)));
+        assert_eq!(1, duration_to_millis(Duration::from_nanos(1)));
+        assert_eq!(2, duration_to_millis(Duration::from_micros(1001)));
+        assert_eq!(1000, duration_to_millis(Duration::from_secs(1)));
+        assert_eq!(
+            u32::MAX - 1,
+            duration_to_millis(Duration::from_secs(u64::MAX))
+        );
+    }
+}
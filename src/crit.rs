@@ -1,20 +1,93 @@
-use crate::common::{PoisonableCriticalSection, POISONABLE_ZEROED};
-use crate::EnteredCritical;
+use crate::common::SectionInner;
+#[cfg(feature = "std")]
+use crate::common::SPINS_BEFORE_YIELD;
+#[cfg(feature = "CriticalStatic")]
+use crate::common::{CriticalStorage, POISONABLE_ZEROED};
+#[cfg(feature = "deadlock-detection")]
+use crate::lock_order;
+#[cfg(feature = "metrics")]
+use crate::CriticalStats;
+#[cfg(feature = "CriticalStatic")]
+use crate::{crit_static::Init, CriticalStaticRef};
+use crate::{
+    Backoff, CriticalSectionConfig, EnteredCritical, Lockable, PoisonPolicy, SectionId, SpinCount,
+};
+
+use static_assertions::assert_not_impl_all;
 
 use crate::wrapper::{
-    delete_cs, enter_cs, init_cs, init_cs_with_spin_count, set_cs_spin_count, try_enter_cs,
+    delete_cs, enter_cs, init_cs, init_cs_ex, init_cs_with_spin_count, processor_count,
+    set_cs_spin_count, supports_cs_ex, try_enter_cs, CRITICAL_SECTION, ERROR_BUSY,
 };
 
-use winapi::um::minwinbase::CRITICAL_SECTION;
-
+#[cfg(feature = "poison-result")]
+use std::sync::{LockResult, PoisonError};
+#[cfg(feature = "std")]
 use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+#[cfg(feature = "CriticalStatic")]
+use core::mem::MaybeUninit;
+use core::{
+    borrow::Borrow,
+    cmp::Ordering,
     fmt::{self, Formatter},
-    sync::Arc,
+    hash::{Hash, Hasher},
+    hint,
+    mem::{self, ManuallyDrop},
+    ops::Deref,
+    ptr,
+    sync::atomic::{self, AtomicPtr},
+};
+#[cfg(feature = "async")]
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
 };
 
+// A SectionInner ready to be initialized and put in an Arc, with the next lock order id if
+// deadlock detection is enabled.
+fn zeroed() -> SectionInner {
+    #[allow(unused_mut)]
+    let mut inner = SectionInner::new();
+    #[cfg(feature = "deadlock-detection")]
+    {
+        inner.lock_id = lock_order::next_id();
+    }
+    inner
+}
+
+// Returns Pending once, waking itself so the executor polls again after other tasks.
+#[cfg(feature = "async")]
+struct YieldNow(bool);
+
+#[cfg(feature = "async")]
+impl Future for YieldNow {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// A critical section shared by reference counting, so it never moves once initialized.
+///
+/// With the deadlock-detection feature, each CriticalSection is given an id in the order
+/// they are created, which its clones share. In debug builds, entering a CriticalSection
+/// while the same thread holds one with a higher id panics, so sections must always be
+/// entered in creation order. Use CriticalSectionBuilder::ignore_lock_order() to opt a
+/// section out of these checks.
 #[derive(Clone)]
 pub struct CriticalSection {
-    inner: Arc<PoisonableCriticalSection>,
+    inner: Arc<SectionInner>,
 }
 
 // Safety: *CRITICAL_SECTION aka lpCriticalSection (effectivity provided by Arc) is Send.
@@ -24,75 +97,1044 @@ unsafe impl Sync for CriticalSection {}
 
 impl PartialEq for CriticalSection {
     fn eq(&self, other: &Self) -> bool {
-        &*self.inner as *const PoisonableCriticalSection == &*other.inner as *const _
+        Arc::ptr_eq(&self.inner, &other.inner)
     }
 }
 impl Eq for CriticalSection {}
 
+// Hash and Ord by identity, consistent with PartialEq.
+impl Hash for CriticalSection {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.inner) as usize).hash(state)
+    }
+}
+impl PartialOrd for CriticalSection {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for CriticalSection {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (Arc::as_ptr(&self.inner) as usize).cmp(&(Arc::as_ptr(&other.inner) as usize))
+    }
+}
+
 impl CriticalSection {
+    /// Creates a new CriticalSection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section cannot be initialized. See try_new().
     pub fn new() -> Self {
-        let inner = Arc::new(POISONABLE_ZEROED);
-        let ptr = &inner.critical as *const _ as *mut CRITICAL_SECTION;
-        // Safety: ptr is to a brand new CRITICAL_SECTION object that
-        // will not be moved in memory. Might panic.
-        unsafe { init_cs(ptr) }
-        Self { inner }
-    }
-    pub fn with_spin_count(spin_count: u32) -> Self {
-        let inner = Arc::new(POISONABLE_ZEROED);
-        let ptr = &inner.critical as *const _ as *mut CRITICAL_SECTION;
-        // Safety: Never fails, and ptr is to a brand new
-        // CRITICAL_SECTION object that will not be moved in memory.
-        unsafe { init_cs_with_spin_count(ptr, spin_count) }
-        Self { inner }
+        Self::try_new().expect("Failed to initialize critical section")
+    }
+    /// Creates a new CriticalSection which will be initialized with the provided spin_count.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section cannot be initialized. See try_with_spin_count().
+    pub fn with_spin_count(spin_count: impl Into<SpinCount>) -> Self {
+        Self::try_with_spin_count(spin_count).expect("Failed to initialize critical section")
+    }
+    /// Creates a new CriticalSection, returning the OS error code if it cannot be initialized.
+    pub fn try_new() -> Result<Self, u32> {
+        // Safety: init_cs is given a brand new CRITICAL_SECTION object
+        unsafe { Self::try_init(zeroed(), |ptr| init_cs(ptr)) }
+    }
+    /// Creates a new CriticalSection which will be initialized with the provided spin_count,
+    /// returning the OS error code if it cannot be initialized.
+    pub fn try_with_spin_count(spin_count: impl Into<SpinCount>) -> Result<Self, u32> {
+        let spin_count = spin_count.into().get();
+        // Safety: init_cs_with_spin_count is given a brand new CRITICAL_SECTION object
+        unsafe { Self::try_init(zeroed(), |ptr| init_cs_with_spin_count(ptr, spin_count)) }
+    }
+    /// Initializes a critical section in caller-provided storage rather than in an Arc, and
+    /// returns a CriticalStaticRef to it, for callers which manage their own memory, such as
+    /// a pool of static slots. The storage does not need to be zeroed first.
+    ///
+    /// Since the storage is borrowed mutably for 'static, it is only ever reachable through
+    /// the returned reference afterwards, and can never be reused or freed. The critical
+    /// section is never deleted, just like a CriticalStatic.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section cannot be initialized.
+    #[cfg(feature = "CriticalStatic")]
+    pub fn in_place(storage: &'static mut MaybeUninit<CriticalStorage>) -> CriticalStaticRef<Init> {
+        let CriticalStorage(inner) = storage.write(CriticalStorage(POISONABLE_ZEROED));
+        // Safety: inner is brand new and borrowed for 'static, so it never moves.
+        unsafe { init_cs(inner.critical.get()) }.expect("Failed to initialize critical section");
+        CriticalStaticRef::from_initialized(inner)
+    }
+    /// Creates a new CriticalSection which must not be entered recursively. In debug builds,
+    /// entering it from the thread which already holds it panics instead of succeeding. In
+    /// release builds it behaves exactly like a CriticalSection from new().
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section cannot be initialized. See try_new().
+    pub fn new_non_reentrant() -> Self {
+        let mut inner = zeroed();
+        inner.non_reentrant = true;
+        // Safety: init_cs is given a brand new CRITICAL_SECTION object
+        unsafe { Self::try_init(inner, |ptr| init_cs(ptr)) }
+            .expect("Failed to initialize critical section")
+    }
+    /// Creates a new CriticalSection which threads enter in roughly the order they called
+    /// enter(). Each thread takes a ticket and waits, spinning and then yielding, until its
+    /// ticket is served before entering the native critical section. This prevents starvation
+    /// under heavy contention at the cost of throughput, since the next thread in line must be
+    /// scheduled before anyone can enter. Recursive entry does not take a ticket.
+    ///
+    /// try_enter() still fails fast, including when other threads are waiting in line even if
+    /// the section itself is free. Code entering through as_raw() bypasses the ordering.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section cannot be initialized. See try_new().
+    pub fn new_fair() -> Self {
+        let mut inner = zeroed();
+        inner.fair = true;
+        // Safety: init_cs is given a brand new CRITICAL_SECTION object
+        unsafe { Self::try_init(inner, |ptr| init_cs(ptr)) }
+            .expect("Failed to initialize critical section")
+    }
+    /// Creates a new CriticalSection without the debug information block Windows would
+    /// otherwise allocate from the process heap for it, which adds up across many sections.
+    /// Debugging tools which rely on that information, such as the `!locks` debugger
+    /// extension, will not see it. Equivalent to the builder with no_debug_info(true).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section cannot be initialized. See try_new().
+    pub fn new_no_debug_info() -> Self {
+        Self::builder()
+            .no_debug_info(true)
+            .build()
+            .expect("Failed to initialize critical section")
+    }
+    /// Creates a new CriticalSection with a name, which is shown in its Debug output, tracing
+    /// events, and deadlock-detection panics.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the critical section cannot be initialized. See try_new().
+    pub fn new_named(name: &'static str) -> Self {
+        let mut inner = zeroed();
+        inner.name = Some(name);
+        // Safety: init_cs is given a brand new CRITICAL_SECTION object
+        unsafe { Self::try_init(inner, |ptr| init_cs(ptr)) }
+            .expect("Failed to initialize critical section")
+    }
+    /// Returns a CriticalSectionBuilder, for setting initialization flags.
+    pub fn builder() -> CriticalSectionBuilder {
+        CriticalSectionBuilder::new()
+    }
+    /// Returns whether InitializeCriticalSectionEx is available, which it is on Windows Vista
+    /// and later, but may not be under Wine or other Windows-compatible runtimes. This is
+    /// checked once, and the result cached.
+    ///
+    /// Without it, the builder's no_debug_info() and dynamic_spin() have no effect, and
+    /// CriticalSections are initialized with only a spin count.
+    pub fn supports_ex() -> bool {
+        supports_cs_ex()
+    }
+    // Safety: init must initialize the CRITICAL_SECTION it is given, or return an error.
+    unsafe fn try_init(
+        inner: SectionInner,
+        init: impl FnOnce(*mut CRITICAL_SECTION) -> Result<(), u32>,
+    ) -> Result<Self, u32> {
+        // CriticalSection itself is Send and Sync; the Arc is never shared any other way.
+        #[allow(clippy::arc_with_non_send_sync)]
+        let mut inner = Arc::new(inner);
+        let unique = Arc::get_mut(&mut inner).expect("new Arc is unique");
+        // ptr is to a brand new CRITICAL_SECTION object that will not be moved in memory.
+        init(unique.critical.get_mut())?;
+        // Only now does dropping the last reference need to delete it.
+        unique.needs_delete = true;
+        Ok(Self { inner })
     }
     #[allow(non_snake_case)]
     fn lpCriticalSection(&self) -> *mut CRITICAL_SECTION {
         self.inner.critical.get()
     }
-    pub fn enter<'c>(&'c self) -> EnteredCritical<'c> {
-        // Safety: might panic, no return value. Naturally thread-safe.
-        unsafe {
+    /// Enters the critical section, blocking until it is available. This will not deadlock
+    /// if the calling thread is already in the critical section.
+    ///
+    /// With the poison-result feature, this instead returns an error if the critical
+    /// section is poisoned. The guard can still be recovered with `PoisonError::into_inner`.
+    #[cfg(not(feature = "poison-result"))]
+    pub fn enter(&self) -> EnteredCritical<'_> {
+        self.enter_unchecked()
+    }
+    /// Enters the critical section, blocking until it is available. This will not deadlock
+    /// if the calling thread is already in the critical section.
+    ///
+    /// Returns an error if the critical section is poisoned. The guard can still be
+    /// recovered with `PoisonError::into_inner`.
+    #[cfg(feature = "poison-result")]
+    pub fn enter(&self) -> LockResult<EnteredCritical<'_>> {
+        let entered = self.enter_unchecked();
+        if entered.is_poisoned() {
+            Err(PoisonError::new(entered))
+        } else {
+            Ok(entered)
+        }
+    }
+    /// Enters the critical section, returning the guard whether or not it is poisoned.
+    pub fn enter_unchecked<'c>(&'c self) -> EnteredCritical<'c> {
+        // Safety: no return value. Naturally thread-safe.
+        #[cfg(feature = "deadlock-detection")]
+        lock_order::check(self.inner.lock_id, self.inner.name);
+        let entered = unsafe {
+            self.inner.wait_turn();
             enter_cs(self.lpCriticalSection());
             EnteredCritical::new(&self.inner)
+        };
+        #[cfg(feature = "deadlock-detection")]
+        lock_order::entered(self.inner.lock_id, self.inner.name);
+        self.check_reentry(entered)
+    }
+    /// Enters the critical section like enter_unchecked(), returning a guard which holds its
+    /// own reference to the CriticalSection instead of borrowing it. The guard is 'static,
+    /// so it can be stored anywhere, but must still be left on the entering thread.
+    pub fn enter_owned(&self) -> OwnedEnteredCritical {
+        let critical = self.clone();
+        let entered = self.enter_unchecked();
+        // Safety: the guard keeps critical, and so the PoisonableCriticalSection, alive until
+        // after entered is dropped.
+        let entered =
+            unsafe { mem::transmute::<EnteredCritical<'_>, EnteredCritical<'static>>(entered) };
+        OwnedEnteredCritical {
+            entered,
+            _critical: critical,
         }
     }
     pub fn try_enter<'c>(&'c self) -> Option<EnteredCritical<'c>> {
+        let entered = self.try_enter_unrecorded();
+        if entered.is_none() {
+            self.inner.record_try_enter_failure();
+        }
+        entered
+    }
+    // try_enter() without counting a failure, for callers which retry and count once.
+    fn try_enter_unrecorded(&self) -> Option<EnteredCritical<'_>> {
+        if !self.inner.try_take_turn() {
+            return None;
+        }
         // Safety: returns non-zero if we are in critical section when call returns.
         // Naturally thread-safe.
         unsafe {
             match try_enter_cs(self.lpCriticalSection()) {
-                0 => None,
-                _ => Some(EnteredCritical::new(&self.inner)),
+                0 => {
+                    self.inner.end_turn();
+                    None
+                }
+                _ => {
+                    #[cfg(feature = "deadlock-detection")]
+                    lock_order::entered(self.inner.lock_id, self.inner.name);
+                    Some(self.check_reentry(EnteredCritical::new(&self.inner)))
+                }
+            }
+        }
+    }
+    // Panics in debug builds if this is a non-reentrant section being entered recursively,
+    // or if the recursion count has overflowed, which Windows does not check for.
+    fn check_reentry<'c>(&self, entered: EnteredCritical<'c>) -> EnteredCritical<'c> {
+        if !cfg!(debug_assertions) {
+            return entered;
+        }
+        let recursion_count = entered.recursion_count();
+        if self.inner.non_reentrant && recursion_count > 1 {
+            // Leave first so the outer guard still holds it exactly once.
+            drop(entered);
+            panic!("CriticalSection is already entered by the current thread")
+        }
+        if recursion_count < 0 {
+            // Leaving undoes the wrap, so the outer guards can still leave.
+            drop(entered);
+            panic!("CriticalSection recursion count overflowed, likely from runaway recursion")
+        }
+        entered
+    }
+    /// Like try_enter(), but tells apart entering for the first time from re-entering a
+    /// critical section the calling thread already holds, such as to run one-time setup
+    /// only on first entry.
+    pub fn try_enter_distinct(&self) -> TryEnterStatus<'_> {
+        match self.try_enter() {
+            // Only the owning thread writes RecursionCount, so it is exact while entered.
+            Some(entered) if entered.recursion_count() > 1 => TryEnterStatus::Reentered(entered),
+            Some(entered) => TryEnterStatus::Acquired(entered),
+            None => TryEnterStatus::Busy,
+        }
+    }
+    /// Like try_enter(), but if the critical section is held by another thread, retries up
+    /// to `spins` more times with a spin loop hint between attempts before giving up. This
+    /// suits critical sections which are only ever held briefly. A spins of 0 behaves like
+    /// try_enter().
+    ///
+    /// While another thread is seen owning the critical section, attempts are skipped
+    /// without calling TryEnterCriticalSection. The final attempt is always made.
+    pub fn try_enter_spinning(&self, spins: u32) -> Option<EnteredCritical<'_>> {
+        self.try_enter_budget(spins).entered
+    }
+    /// Like try_enter_spinning(), but also returns how many spins it took, for measuring
+    /// contention in terms of spin counts rather than time, such as to pick a value for
+    /// set_spin_count(). The count is 0 if the first attempt succeeds, and max_spins if
+    /// every attempt failed. With the metrics feature, a failure is only counted once per
+    /// call, however many attempts were made.
+    pub fn try_enter_budget(&self, max_spins: u32) -> TryEnterResult<'_> {
+        for spins in 0..max_spins {
+            if self.probably_free() {
+                if let Some(entered) = self.try_enter_unrecorded() {
+                    return TryEnterResult {
+                        entered: Some(entered),
+                        spins,
+                    };
+                }
+            }
+            hint::spin_loop();
+        }
+        TryEnterResult {
+            entered: self.try_enter(),
+            spins: max_spins,
+        }
+    }
+    /// Blocks until no other thread holds the critical section, then returns without holding
+    /// it. This briefly enters and leaves, which guarantees the section was free at some
+    /// moment, though another thread may have entered again by the time this returns. If the
+    /// calling thread already holds it, this returns immediately.
+    pub fn wait_until_unlocked(&self) {
+        // Entering again would panic for a section from new_non_reentrant().
+        if self.held_by_current_thread() {
+            return;
+        }
+        self.enter_unchecked().leave()
+    }
+    /// Enters the critical section, runs the provided closure, and leaves. If the closure
+    /// panics, the critical section is still left and becomes poisoned.
+    pub fn with_lock<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _entered = self.enter_unchecked();
+        f()
+    }
+    /// Like with_lock(), but returns None without running the closure if the critical
+    /// section cannot be entered immediately.
+    pub fn try_with_lock<R>(&self, f: impl FnOnce() -> R) -> Option<R> {
+        let _entered = self.try_enter()?;
+        Some(f())
+    }
+    /// Tries to enter the critical section, giving up once the provided duration has passed.
+    /// This repeatedly calls try_enter(), spinning briefly between attempts before falling
+    /// back to yielding the thread, so it is only suitable for coarse timeouts.
+    #[cfg(feature = "std")]
+    pub fn try_enter_for(&self, dur: Duration) -> Option<EnteredCritical<'_>> {
+        match Instant::now().checked_add(dur) {
+            Some(deadline) => self.try_enter_until(deadline),
+            None => Some(self.enter_unchecked()),
+        }
+    }
+    /// Like try_enter_for(), but gives up once the provided deadline has passed.
+    #[cfg(feature = "std")]
+    pub fn try_enter_until(&self, deadline: Instant) -> Option<EnteredCritical<'_>> {
+        let mut spins = 0;
+        loop {
+            if let Some(entered) = self.try_enter() {
+                return Some(entered);
+            } else if Instant::now() >= deadline {
+                return None;
+            } else if spins < SPINS_BEFORE_YIELD {
+                spins += 1;
+                hint::spin_loop();
+            } else {
+                self.inner.record_spin_fallback();
+                thread::yield_now();
+            }
+        }
+    }
+    /// Enters the critical section like enter_unchecked(), but first calls try_enter()
+    /// repeatedly, asking `backoff` to wait between attempts, and only blocks once it has
+    /// completed. This allows tuning how contention is handled, for example with an
+    /// ExponentialBackoff. The backoff is not reset, so it can be reused across calls.
+    pub fn enter_with_backoff(&self, backoff: &mut impl Backoff) -> EnteredCritical<'_> {
+        loop {
+            if let Some(entered) = self.try_enter() {
+                return entered;
+            } else if backoff.is_completed() {
+                return self.enter_unchecked();
+            }
+            backoff.snooze();
+        }
+    }
+    /// Enters the critical section like enter_unchecked(), returning a guard which measures
+    /// how long it is held. With the tracing feature, the held duration is logged at debug
+    /// level when it is left, including when dropped while panicking.
+    #[cfg(feature = "std")]
+    pub fn enter_timed(&self) -> TimedEnteredCritical<'_> {
+        TimedEnteredCritical::new(self.enter_unchecked(), None)
+    }
+    /// Like enter_timed(), but also passes the held duration to `report` just after leaving,
+    /// including when dropped while panicking. A panic inside `report` in that case aborts.
+    #[cfg(feature = "std")]
+    pub fn enter_timed_with<'c>(
+        &'c self,
+        report: impl FnOnce(Duration) + 'c,
+    ) -> TimedEnteredCritical<'c> {
+        TimedEnteredCritical::new(self.enter_unchecked(), Some(Box::new(report)))
+    }
+    /// Enters the critical section without blocking the thread, for use in async code. This
+    /// is a spin-yield future: it calls try_enter() each time it is polled, and if that fails
+    /// it wakes itself and returns Pending so the executor can run other tasks first. It is
+    /// never parked, so under heavy contention it keeps the executor busy.
+    ///
+    /// The returned guard must not be held across an `.await`. Another task on the same
+    /// thread would enter recursively rather than waiting, and the guard cannot be moved to
+    /// another thread.
+    #[cfg(feature = "async")]
+    pub async fn lock_async(&self) -> EnteredCritical<'_> {
+        self.lock_async_with(|| YieldNow(false)).await
+    }
+    /// Like lock_async(), but awaits the future returned by `yield_now` between attempts
+    /// instead, so an executor's own yield function can be used.
+    #[cfg(feature = "async")]
+    pub async fn lock_async_with<F: Future<Output = ()>>(
+        &self,
+        mut yield_now: impl FnMut() -> F,
+    ) -> EnteredCritical<'_> {
+        loop {
+            if let Some(entered) = self.try_enter() {
+                return entered;
+            }
+            yield_now().await
+        }
+    }
+    /// Enters both critical sections like enter_unchecked(), always in the same order for a
+    /// given pair regardless of which is `self`, so threads entering the same two sections
+    /// through this method cannot deadlock against each other. The guards are returned in
+    /// argument order. Passing the same section twice enters it recursively.
+    ///
+    /// The order is by address, or by lock order id first with the deadlock-detection
+    /// feature, so it never trips the lock order checks.
+    pub fn enter_both<'c>(
+        &'c self,
+        other: &'c CriticalSection,
+    ) -> (EnteredCritical<'c>, EnteredCritical<'c>) {
+        if self.enters_before(other) {
+            let first = self.enter_unchecked();
+            (first, other.enter_unchecked())
+        } else {
+            let second = other.enter_unchecked();
+            (self.enter_unchecked(), second)
+        }
+    }
+    /// Like enter_both(), but uses try_enter() for each section in the same order, returning
+    /// None if either cannot be entered immediately. The first section is left again if the
+    /// second fails.
+    pub fn try_enter_both<'c>(
+        &'c self,
+        other: &'c CriticalSection,
+    ) -> Option<(EnteredCritical<'c>, EnteredCritical<'c>)> {
+        if self.enters_before(other) {
+            let first = self.try_enter()?;
+            Some((first, other.try_enter()?))
+        } else {
+            let second = other.try_enter()?;
+            Some((self.try_enter()?, second))
+        }
+    }
+    /// Enters every section in the slice like enter_unchecked(), in the same canonical order
+    /// as enter_both(), so threads entering overlapping sets through this method cannot
+    /// deadlock against each other. The guards are returned in slice order. A section which
+    /// appears more than once is entered recursively.
+    pub fn enter_all(sections: &[CriticalSection]) -> Vec<EnteredCritical<'_>> {
+        let mut order: Vec<usize> = (0..sections.len()).collect();
+        let mut slots: Vec<_> = sections.iter().map(|_| None).collect();
+        Self::enter_sorted(&mut order, |i| &sections[i], &mut slots, true);
+        slots.into_iter().map(Option::unwrap).collect()
+    }
+    /// Like enter_all(), but uses try_enter() for each section, returning None if any cannot
+    /// be entered immediately. Sections already entered are left again, in reverse order.
+    pub fn try_enter_all(sections: &[CriticalSection]) -> Option<Vec<EnteredCritical<'_>>> {
+        let mut order: Vec<usize> = (0..sections.len()).collect();
+        let mut slots: Vec<_> = sections.iter().map(|_| None).collect();
+        if !Self::enter_sorted(&mut order, |i| &sections[i], &mut slots, false) {
+            return None;
+        }
+        Some(slots.into_iter().map(Option::unwrap).collect())
+    }
+    /// Like enter_all(), for a fixed number of sections, without allocating.
+    pub fn enter_array<'c, const N: usize>(
+        sections: [&'c CriticalSection; N],
+    ) -> [EnteredCritical<'c>; N] {
+        let mut order: [usize; N] = core::array::from_fn(|i| i);
+        let mut slots: [_; N] = core::array::from_fn(|_| None);
+        Self::enter_sorted(&mut order, |i| sections[i], &mut slots, true);
+        slots.map(Option::unwrap)
+    }
+    /// Like try_enter_all(), for a fixed number of sections, without allocating.
+    pub fn try_enter_array<'c, const N: usize>(
+        sections: [&'c CriticalSection; N],
+    ) -> Option<[EnteredCritical<'c>; N]> {
+        let mut order: [usize; N] = core::array::from_fn(|i| i);
+        let mut slots: [_; N] = core::array::from_fn(|_| None);
+        if !Self::enter_sorted(&mut order, |i| sections[i], &mut slots, false) {
+            return None;
+        }
+        Some(slots.map(Option::unwrap))
+    }
+    // Sorts the indices in order into canonical order and enters each section into its slot.
+    // Unless blocking, gives up on the first failure, leaving everything entered so far.
+    fn enter_sorted<'c>(
+        order: &mut [usize],
+        section: impl Fn(usize) -> &'c CriticalSection,
+        slots: &mut [Option<EnteredCritical<'c>>],
+        blocking: bool,
+    ) -> bool {
+        order.sort_unstable_by(|&a, &b| section(a).entry_cmp(section(b)));
+        for &i in order.iter() {
+            slots[i] = if blocking {
+                Some(section(i).enter_unchecked())
+            } else {
+                section(i).try_enter()
+            };
+            if slots[i].is_none() {
+                for &j in order.iter().rev() {
+                    slots[j] = None;
+                }
+                return false;
             }
         }
+        true
+    }
+    // The canonical order for enter_both(). Untracked sections have lock_id 0, so with
+    // deadlock detection they sort before tracked ones and then by address.
+    fn enters_before(&self, other: &CriticalSection) -> bool {
+        self.entry_cmp(other) != Ordering::Greater
+    }
+    fn entry_cmp(&self, other: &CriticalSection) -> Ordering {
+        #[cfg(feature = "deadlock-detection")]
+        if self.inner.lock_id != other.inner.lock_id {
+            return self.inner.lock_id.cmp(&other.inner.lock_id);
+        }
+        self.cmp(other)
     }
-    pub fn set_spin_count(&self, spin_count: u32) -> u32 {
+    /// Sets the spin count of this critical section, and returns the old value. Windows
+    /// ignores the spin count on single-processor systems. See effective_spin_count().
+    pub fn set_spin_count(&self, spin_count: impl Into<SpinCount>) -> u32 {
+        #[cfg(feature = "tracing")]
+        if processor_count() == 1 {
+            tracing::debug!(
+                critical = ?self.lpCriticalSection(),
+                "spin count set on a single-processor system, where Windows ignores it"
+            );
+        }
         // Safety: cannot fail. Returns previous spin_count. Naturally thread-safe.
-        unsafe { set_cs_spin_count(self.lpCriticalSection(), spin_count) }
+        unsafe { set_cs_spin_count(self.lpCriticalSection(), spin_count.into().get()) }
+    }
+    /// Sets the spin count to `spin` while running `f`, then restores the previous spin
+    /// count, including if `f` panics. The spin count belongs to the critical section, so
+    /// other threads entering it meanwhile use the boosted value too, and overlapping scopes
+    /// on different threads may restore each other's values.
+    pub fn with_spin_count_scope<R>(&self, spin: u32, f: impl FnOnce() -> R) -> R {
+        struct Restore<'c>(&'c CriticalSection, u32);
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                self.0.set_spin_count(self.1);
+            }
+        }
+        let _restore = Restore(self, self.set_spin_count(spin));
+        f()
+    }
+    /// Returns the current spin count of this Critical Section, without the side effects
+    /// of set_spin_count().
+    pub fn spin_count(&self) -> u32 {
+        self.inner.spin_count()
+    }
+    /// Returns the spin count Windows actually uses, which is 0 on single-processor systems
+    /// regardless of what it was set to, and spin_count() otherwise. The processor count is
+    /// checked once, and the result cached.
+    pub fn effective_spin_count(&self) -> u32 {
+        if processor_count() == 1 {
+            0
+        } else {
+            self.spin_count()
+        }
+    }
+    /// Returns true if a thread panicked while holding this critical section, without
+    /// entering it. Unless the calling thread holds the critical section, another thread
+    /// may poison it or clear the poison at any moment after this returns.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+    /// Enters the critical section, clears the poison flag, and leaves.
+    pub fn clear_poison(&self) {
+        self.enter_unchecked().clear_poison()
+    }
+    /// Deletes and re-initializes the underlying CRITICAL_SECTION, keeping its spin count, and
+    /// clears the poison flag. This is a recovery path for a section whose state is suspect,
+    /// short of replacing it entirely.
+    ///
+    /// This takes `&mut self` so that no other thread can be using this handle, and fails with
+    /// ERROR_BUSY if there are any other references to the section, such as clones, owned
+    /// guards, or pointers from into_raw(). Together these guarantee no thread is entered or
+    /// waiting. Any InitializeCriticalSectionEx flags from CriticalSectionBuilder are not kept.
+    pub fn reinit(&mut self) -> Result<(), u32> {
+        let inner = Arc::get_mut(&mut self.inner).ok_or(ERROR_BUSY)?;
+        let spin_count = inner.spin_count();
+        let ptr = inner.critical.get_mut() as *mut CRITICAL_SECTION;
+        // Safety: we have exclusive access, so no thread can be entered or waiting. Neither
+        // call can fail, and the spin count is restored afterwards.
+        unsafe {
+            delete_cs(ptr);
+            if let Err(code) = init_cs(ptr) {
+                // Nothing left to delete when the last reference is dropped.
+                inner.needs_delete = false;
+                return Err(code);
+            }
+            set_cs_spin_count(ptr, spin_count);
+        }
+        inner.clear_poison_mut();
+        Ok(())
+    }
+    /// Deletes the underlying CRITICAL_SECTION now, for a controlled teardown instead of
+    /// whenever the last reference happens to be dropped. Fails, returning the handle, if
+    /// there are any other references to the section, as with reinit(), or if it is held,
+    /// such as by a guard which was forgotten.
+    pub fn try_delete(mut self) -> Result<(), CriticalSection> {
+        match Arc::get_mut(&mut self.inner) {
+            // Dropping the only reference deletes it.
+            Some(inner) if !inner.is_locked() => Ok(()),
+            _ => Err(self),
+        }
+    }
+    /// Returns the name given by new_named() or CriticalSectionBuilder::name(), if any.
+    pub fn name(&self) -> Option<&'static str> {
+        self.inner.name
+    }
+    /// Returns the id of the thread currently holding this critical section, or None if
+    /// it is not held. This is only a snapshot intended for debugging: unless the calling
+    /// thread is the owner, the owner may change at any moment after this returns.
+    pub fn owning_thread_id(&self) -> Option<u32> {
+        self.inner.owning_thread_id()
+    }
+    /// Returns true if any thread currently holds this critical section, without
+    /// attempting to enter it. This is a best-effort snapshot which may be stale as soon
+    /// as it is returned, and is intended for monitoring rather than synchronization.
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+    /// Returns the LockCount field of the underlying CRITICAL_SECTION verbatim, for low-level
+    /// diagnostics. Its encoding is undocumented and has changed between Windows versions, so
+    /// treat it as a best-effort snapshot which may be stale as soon as it is returned.
+    pub fn raw_lock_count(&self) -> i32 {
+        self.inner.lock_count()
+    }
+    /// Returns roughly how many threads are waiting to enter this critical section, by
+    /// interpreting LockCount as Windows Vista and later encode it. Like raw_lock_count(),
+    /// this is best-effort, and only suitable for telemetry.
+    pub fn approx_waiter_count(&self) -> u32 {
+        self.inner.approx_waiter_count()
+    }
+    /// Returns false if another thread appears to hold this critical section, predicting
+    /// whether try_enter() would succeed without calling into Windows. This is advisory only:
+    /// the owner may change at any moment, so a true result may still be followed by a
+    /// failed try_enter() and the reverse. A fair section may also refuse try_enter() while
+    /// other threads wait in line. Useful for skipping attempts which are obviously doomed.
+    pub fn probably_free(&self) -> bool {
+        !self.inner.held_by_other_thread()
+    }
+    /// Returns true if the calling thread holds this critical section, for assertions such
+    /// as `debug_assert!(critical.held_by_current_thread())`. The fields read may be changed
+    /// by other threads entering and leaving concurrently, but no other thread can make this
+    /// thread the owner, so the result is reliable as a self-check.
+    pub fn held_by_current_thread(&self) -> bool {
+        self.inner.held_by_current_thread()
+    }
+    /// Returns a snapshot of the counters kept for this critical section, which are shared
+    /// by all of its clones.
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> CriticalStats {
+        self.inner.stats()
+    }
+    /// Returns a pointer to the underlying CRITICAL_SECTION, for use with other APIs which
+    /// take an LPCRITICAL_SECTION.
+    ///
+    /// The pointer is valid for as long as any clone of this CriticalSection exists. It must
+    /// not be deleted or re-initialized, and must only be left by a thread which entered it
+    /// through the same pointer. Poisoning only applies when entered through this crate.
+    pub fn as_raw(&self) -> *mut CRITICAL_SECTION {
+        self.lpCriticalSection()
+    }
+    /// Consumes the CriticalSection, returning a pointer to the underlying CRITICAL_SECTION
+    /// without changing the reference count. This handle's reference is kept alive until
+    /// the pointer is passed to from_raw(), otherwise the critical section is leaked.
+    ///
+    /// The same rules as as_raw() apply to using the pointer in the meantime.
+    pub fn into_raw(self) -> *mut CRITICAL_SECTION {
+        let this = ManuallyDrop::new(self);
+        // Safety: this is never used or dropped again, so inner is moved out exactly once.
+        let inner = unsafe { ptr::read(&this.inner) };
+        Arc::into_raw(inner) as *mut CRITICAL_SECTION
+    }
+    /// Leaks this CriticalSection, returning a reference that lives for the rest of the
+    /// program. The reference is Copy, so it can be handed to any number of threads without
+    /// cloning the Arc. The underlying critical section is never deleted.
+    pub fn leak(self) -> &'static CriticalSection {
+        Box::leak(Box::new(self))
+    }
+    /// Reconstructs a CriticalSection from a pointer returned by into_raw(), taking back
+    /// the reference it held.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must have been returned by into_raw(), and each such pointer may only be
+    /// passed to from_raw() once.
+    ///
+    /// A CRITICAL_SECTION initialized elsewhere, such as by C code, cannot be adopted, since
+    /// it must not move out of the memory it was initialized in, and a CriticalSection keeps
+    /// its CRITICAL_SECTION inside its own Arc. Use raw::RawCriticalSection::from_ptr() to
+    /// enter and leave one of those, or as_raw() to share a CriticalSection with C code.
+    pub unsafe fn from_raw(ptr: *mut CRITICAL_SECTION) -> Self {
+        // SectionInner and PoisonableCriticalSection are repr(C) with the CRITICAL_SECTION
+        // first.
+        Self {
+            inner: Arc::from_raw(ptr as *const SectionInner),
+        }
+    }
+    // Safety: inner must be inside the Arc of a live CriticalSection.
+    pub(crate) unsafe fn from_inner(inner: &SectionInner) -> Self {
+        let ptr = inner as *const SectionInner;
+        Arc::increment_strong_count(ptr);
+        Self {
+            inner: Arc::from_raw(ptr),
+        }
     }
 }
 
-impl Drop for CriticalSection {
-    fn drop(&mut self) {
-        if Arc::strong_count(&self.inner) == 1 {
-            // Safety: we have exclusive access by knowing strong count is one in drop,
-            // we never created any weak refs, and FFI call never fails
-            unsafe { delete_cs(self.lpCriticalSection()) }
+impl AsRef<SectionId> for CriticalSection {
+    fn as_ref(&self) -> &SectionId {
+        SectionId::of(&self.inner)
+    }
+}
+impl Borrow<SectionId> for CriticalSection {
+    fn borrow(&self) -> &SectionId {
+        SectionId::of(&self.inner)
+    }
+}
+
+impl Lockable for CriticalSection {
+    type Guard<'a> = EnteredCritical<'a>;
+    fn enter(&self) -> EnteredCritical<'_> {
+        self.enter_unchecked()
+    }
+    fn try_enter(&self) -> Option<EnteredCritical<'_>> {
+        CriticalSection::try_enter(self)
+    }
+}
+
+/// Configures and creates a CriticalSection using InitializeCriticalSectionEx.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CriticalSectionBuilder {
+    spin_count: SpinCount,
+    no_debug_info: bool,
+    dynamic_spin: bool,
+    poison_policy: PoisonPolicy,
+    name: Option<&'static str>,
+    #[cfg(feature = "deadlock-detection")]
+    ignore_lock_order: bool,
+}
+
+impl CriticalSectionBuilder {
+    /// Creates a builder with a spin count of 0, no flags set, and PoisonPolicy::Flag.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the spin count the CriticalSection will be initialized with.
+    pub fn spin_count(mut self, spin_count: impl Into<SpinCount>) -> Self {
+        self.spin_count = spin_count.into();
+        self
+    }
+    /// If set, Windows will not allocate debug information for the CriticalSection, which
+    /// saves memory but hides it from debugger extensions such as `!locks`.
+    pub fn no_debug_info(mut self, no_debug_info: bool) -> Self {
+        self.no_debug_info = no_debug_info;
+        self
+    }
+    /// If set, Windows adjusts the spin count dynamically, using the given spin count as a
+    /// starting point.
+    pub fn dynamic_spin(mut self, dynamic_spin: bool) -> Self {
+        self.dynamic_spin = dynamic_spin;
+        self
+    }
+    /// Sets what happens when a thread panics while inside the CriticalSection. The default
+    /// is PoisonPolicy::Flag.
+    pub fn on_poison(mut self, poison_policy: PoisonPolicy) -> Self {
+        self.poison_policy = poison_policy;
+        self
+    }
+    /// Sets a name for the CriticalSection, as with CriticalSection::new_named().
+    pub fn name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+    /// If set, the CriticalSection is not given a lock order id, and is ignored by deadlock
+    /// detection.
+    #[cfg(feature = "deadlock-detection")]
+    pub fn ignore_lock_order(mut self, ignore_lock_order: bool) -> Self {
+        self.ignore_lock_order = ignore_lock_order;
+        self
+    }
+    /// Creates the CriticalSection, returning the OS error code if it cannot be initialized.
+    pub fn build(&self) -> Result<CriticalSection, u32> {
+        let flags = CriticalSectionConfig {
+            spin_count: None,
+            no_debug_info: self.no_debug_info,
+            dynamic_spin: self.dynamic_spin,
         }
+        .flags();
+        let spin_count = self.spin_count.get();
+        let mut inner = zeroed();
+        inner.poison_policy = self.poison_policy;
+        inner.name = self.name;
+        #[cfg(feature = "deadlock-detection")]
+        if self.ignore_lock_order {
+            inner.lock_id = 0;
+        }
+        // Safety: init_cs_ex is given a brand new CRITICAL_SECTION object
+        unsafe { CriticalSection::try_init(inner, |ptr| init_cs_ex(ptr, spin_count, flags)) }
+    }
+}
+
+impl From<CriticalSectionConfig> for CriticalSectionBuilder {
+    fn from(config: CriticalSectionConfig) -> Self {
+        Self::new()
+            .spin_count(config.spin_count())
+            .no_debug_info(config.no_debug_info)
+            .dynamic_spin(config.dynamic_spin)
+    }
+}
+
+impl Default for CriticalSection {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl fmt::Debug for CriticalSection {
     fn fmt(&self, out: &mut Formatter) -> fmt::Result {
-        write!(out, "CriticalSection: {:p}", self.inner)
+        write!(out, "CriticalSection: {:p}", self.inner)?;
+        self.inner.write_name(out)
+    }
+}
+
+/// A CriticalSection which can be constructed in a const context, such as a static. The
+/// CriticalSection is created the first time it is used, through get() or Deref, so
+/// `LAZY.enter()` and `LAZY.clone()` work as they would on a CriticalSection.
+///
+/// # Panics
+///
+/// First use panics if the critical section cannot be initialized.
+pub struct LazyCriticalSection {
+    inner: AtomicPtr<CriticalSection>,
+}
+
+impl LazyCriticalSection {
+    /// Creates a new LazyCriticalSection. No critical section is created until first use.
+    pub const fn new() -> Self {
+        Self {
+            inner: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+    /// Returns the CriticalSection, creating it if this is the first use.
+    pub fn get(&self) -> &CriticalSection {
+        let existing = self.inner.load(atomic::Ordering::Acquire);
+        if !existing.is_null() {
+            // Safety: once set, inner is never changed or freed until drop
+            return unsafe { &*existing };
+        }
+        let new = Box::into_raw(Box::new(CriticalSection::new()));
+        match self.inner.compare_exchange(
+            ptr::null_mut(),
+            new,
+            atomic::Ordering::AcqRel,
+            atomic::Ordering::Acquire,
+        ) {
+            // Safety: new is now owned by self, and is never changed or freed until drop
+            Ok(_) => unsafe { &*new },
+            // Safety: another thread won the race, and new was never shared
+            Err(existing) => unsafe {
+                drop(Box::from_raw(new));
+                &*existing
+            },
+        }
+    }
+}
+
+impl Deref for LazyCriticalSection {
+    type Target = CriticalSection;
+    fn deref(&self) -> &CriticalSection {
+        self.get()
+    }
+}
+
+impl Default for LazyCriticalSection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for LazyCriticalSection {
+    fn drop(&mut self) {
+        let inner = *self.inner.get_mut();
+        if !inner.is_null() {
+            // Safety: we have exclusive access, and inner came from Box::into_raw
+            drop(unsafe { Box::from_raw(inner) });
+        }
+    }
+}
+
+impl fmt::Debug for LazyCriticalSection {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        let existing = self.inner.load(atomic::Ordering::Acquire);
+        if existing.is_null() {
+            write!(out, "LazyCriticalSection: uninitialized")
+        } else {
+            // Safety: once set, inner is never changed or freed until drop
+            write!(out, "Lazy{:?}", unsafe { &*existing })
+        }
+    }
+}
+
+/// An entered CriticalSection which keeps it alive, obtained from enter_owned(). It is left
+/// when dropped, and poisons the CriticalSection if dropped while panicking.
+pub struct OwnedEnteredCritical {
+    // Declared first so that it is dropped, leaving, before the CriticalSection it borrows.
+    entered: EnteredCritical<'static>,
+    _critical: CriticalSection,
+}
+
+// Safety: it is not okay to enter from one thread and leave from another.
+assert_not_impl_all!(OwnedEnteredCritical: Send, Sync);
+
+impl OwnedEnteredCritical {
+    pub fn leave(self) {
+        drop(self)
+    }
+}
+
+// No DerefMut, since swapping the EnteredCritical out would separate it from its section.
+impl Deref for OwnedEnteredCritical {
+    type Target = EnteredCritical<'static>;
+    fn deref(&self) -> &Self::Target {
+        &self.entered
+    }
+}
+
+impl fmt::Debug for OwnedEnteredCritical {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "Owned{:?}", self.entered)
+    }
+}
+
+/// The outcome of CriticalSection::try_enter_distinct().
+#[derive(Debug)]
+pub enum TryEnterStatus<'c> {
+    /// The calling thread did not hold the critical section, and now does.
+    Acquired(EnteredCritical<'c>),
+    /// The calling thread already held the critical section, and has entered it again.
+    Reentered(EnteredCritical<'c>),
+    /// Another thread holds the critical section.
+    Busy,
+}
+
+/// The outcome of CriticalSection::try_enter_budget().
+#[derive(Debug)]
+pub struct TryEnterResult<'c> {
+    /// The entered critical section, or None if every attempt failed.
+    pub entered: Option<EnteredCritical<'c>>,
+    /// How many spins were made before the critical section was entered, or before giving
+    /// up.
+    pub spins: u32,
+}
+
+/// An entered CriticalSection which measures how long it is held, obtained from
+/// enter_timed() or enter_timed_with(). It is left when dropped.
+#[cfg(feature = "std")]
+pub struct TimedEnteredCritical<'c> {
+    entered: ManuallyDrop<EnteredCritical<'c>>,
+    start: Instant,
+    report: Option<Box<dyn FnOnce(Duration) + 'c>>,
+}
+
+#[cfg(feature = "std")]
+impl<'c> TimedEnteredCritical<'c> {
+    fn new(entered: EnteredCritical<'c>, report: Option<Box<dyn FnOnce(Duration) + 'c>>) -> Self {
+        Self {
+            entered: ManuallyDrop::new(entered),
+            start: Instant::now(),
+            report,
+        }
+    }
+    /// Returns how long the critical section has been held so far.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+    pub fn leave(self) {
+        drop(self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'c> Deref for TimedEnteredCritical<'c> {
+    type Target = EnteredCritical<'c>;
+    fn deref(&self) -> &Self::Target {
+        &self.entered
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for TimedEnteredCritical<'_> {
+    fn drop(&mut self) {
+        let held = self.start.elapsed();
+        #[cfg(feature = "tracing")]
+        let (critical, name) = (self.entered.lpCriticalSection(), self.entered.name());
+        // Safety: entered is never used again. Leave before reporting, so reporting does not
+        // extend how long the critical section is held.
+        unsafe { ManuallyDrop::drop(&mut self.entered) }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(critical = ?critical, ?name, ?held, "left timed critical section");
+        if let Some(report) = self.report.take() {
+            report(held)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for TimedEnteredCritical<'_> {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(
+            out,
+            "Timed{:?} (held for {:?})",
+            *self.entered,
+            self.elapsed()
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::CriticalSection;
-    use std::thread;
+    use crate::{
+        CriticalSection, CriticalSectionBuilder, LazyCriticalSection, OwnedEnteredCritical,
+        SpinCount, TryEnterStatus,
+    };
+    #[cfg(feature = "std")]
+    use std::time::{Duration, Instant};
+    use std::{
+        collections::{hash_map::DefaultHasher, BTreeSet},
+        hash::{Hash, Hasher},
+        panic::{self, AssertUnwindSafe},
+        sync::{mpsc, Arc, Barrier},
+        thread,
+    };
 
     /*
         🎶 99 Mutating Threads on the Wall 🎶
@@ -109,7 +1151,7 @@ mod tests {
         for i in 0..99 {
             let crit = critical.clone();
             handles.push(thread::spawn(move || {
-                let entered = crit.enter();
+                let entered = crit.enter_unchecked();
                 if i == 0 {
                     panic!("Take one down")
                 }
@@ -129,7 +1171,8 @@ mod tests {
             }
         }
         assert_eq!(98, unsafe { X });
-        assert!(critical.enter().is_poisoned());
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
+        assert!(critical.enter_unchecked().is_poisoned());
     }
 
     #[test]
@@ -138,4 +1181,1151 @@ mod tests {
         let c2 = c1.clone();
         assert_eq!(c1, c2);
     }
+
+    #[test]
+    fn try_new() {
+        let critical = CriticalSection::try_new().unwrap();
+        assert!(!critical.enter_unchecked().is_poisoned());
+        let critical = CriticalSection::try_with_spin_count(4000).unwrap();
+        assert!(!critical.enter_unchecked().is_poisoned());
+    }
+
+    #[test]
+    fn owning_thread_id() {
+        let critical = CriticalSection::new();
+        assert_eq!(None, critical.owning_thread_id());
+        let crit = critical.clone();
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (leave_tx, leave_rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let entered = crit.enter_unchecked();
+            let id = crate::wrapper::current_thread_id();
+            assert_eq!(Some(id), entered.owning_thread_id());
+            entered_tx.send(id).unwrap();
+            leave_rx.recv().unwrap();
+        });
+        let id = entered_rx.recv().unwrap();
+        assert_eq!(Some(id), critical.owning_thread_id());
+        leave_tx.send(()).unwrap();
+        handle.join().unwrap();
+        assert_eq!(None, critical.owning_thread_id());
+    }
+
+    #[test]
+    fn recursion_count() {
+        let critical = CriticalSection::new();
+        let first = critical.enter_unchecked();
+        assert_eq!(1, first.recursion_count());
+        let second = critical.enter_unchecked();
+        assert_eq!(2, second.recursion_count());
+        assert_eq!(2, first.recursion_count());
+        second.leave();
+        assert_eq!(1, first.recursion_count());
+        first.leave();
+    }
+
+    #[test]
+    fn nested_guards() {
+        let sections: Vec<_> = (1..=3).map(CriticalSection::with_spin_count).collect();
+        let mut guards = crate::NestedGuards::new();
+        for critical in &sections {
+            guards.push(critical.enter_unchecked());
+        }
+        assert_eq!(3, guards.len());
+        let last = guards.pop().unwrap();
+        assert_eq!(3, last.spin_count());
+        drop(last);
+        assert!(!sections[2].is_locked());
+        assert!(sections[0].is_locked() && sections[1].is_locked());
+        guards.release();
+        assert!(sections.iter().all(|critical| !critical.is_locked()));
+    }
+
+    #[test]
+    fn is_locked() {
+        let critical = CriticalSection::new();
+        assert!(!critical.is_locked());
+        let entered = critical.enter_unchecked();
+        assert!(critical.is_locked());
+        entered.leave();
+        assert!(!critical.is_locked());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn try_enter_for() {
+        let critical = CriticalSection::new();
+        let crit = critical.clone();
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (leave_tx, leave_rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let _entered = crit.enter_unchecked();
+            entered_tx.send(()).unwrap();
+            leave_rx.recv().unwrap();
+        });
+        entered_rx.recv().unwrap();
+        let start = Instant::now();
+        assert!(critical.try_enter_for(Duration::from_millis(50)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        leave_tx.send(()).unwrap();
+        assert!(critical.try_enter_for(Duration::from_secs(10)).is_some());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn with_lock() {
+        let critical = CriticalSection::new();
+        assert_eq!(42, critical.with_lock(|| 42));
+        assert!(!critical.is_locked());
+        assert_eq!(Some(7), critical.try_with_lock(|| 7));
+        assert!(!critical.is_locked());
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            critical.with_lock(|| panic!("Poison it"))
+        }))
+        .unwrap_err();
+        assert!(!critical.is_locked());
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
+        assert!(critical.enter_unchecked().is_poisoned());
+    }
+
+    #[test]
+    fn default() {
+        #[derive(Default)]
+        struct Config {
+            critical: CriticalSection,
+        }
+        let config = Config::default();
+        assert!(!config.critical.enter_unchecked().is_poisoned());
+    }
+
+    #[test]
+    fn spin_count() {
+        let critical = CriticalSection::with_spin_count(4000);
+        assert_eq!(4000, critical.spin_count());
+        assert_eq!(4000, critical.set_spin_count(100));
+        assert_eq!(100, critical.spin_count());
+        assert_eq!(100, critical.enter_unchecked().spin_count());
+    }
+
+    #[test]
+    fn reborrow() {
+        fn requires_lock(entered: crate::EnteredCritical<'_>) -> i32 {
+            entered.recursion_count()
+        }
+        let critical = CriticalSection::new();
+        let mut entered = critical.enter_unchecked();
+        assert_eq!(1, requires_lock(entered.reborrow()));
+        entered.reborrow().leave();
+        assert!(critical.held_by_current_thread());
+        let crit = critical.clone();
+        thread::spawn(move || assert!(crit.try_enter().is_none()))
+            .join()
+            .unwrap();
+        entered.leave();
+        assert!(!critical.is_locked());
+    }
+
+    #[test]
+    fn supports_ex() {
+        // Every supported version of Windows has it.
+        assert!(CriticalSection::supports_ex());
+        assert!(CriticalSection::supports_ex());
+    }
+
+    #[test]
+    fn new_no_debug_info() {
+        let critical = CriticalSection::new_no_debug_info();
+        let entered = critical.enter_unchecked();
+        assert!(critical.is_locked());
+        // Windows marks a section without debug info with a DebugInfo of -1.
+        let debug_info = entered.with_raw(|ptr| unsafe { (*ptr).DebugInfo });
+        assert_eq!(-1, debug_info as isize);
+        entered.leave();
+        assert_eq!(0, critical.spin_count());
+    }
+
+    #[test]
+    fn enter_with_backoff() {
+        struct Counting(u32);
+        impl crate::Backoff for Counting {
+            fn snooze(&mut self) {
+                self.0 += 1
+            }
+            fn is_completed(&self) -> bool {
+                self.0 >= 3
+            }
+        }
+        let critical = CriticalSection::new();
+        let mut backoff = Counting(0);
+        critical.enter_with_backoff(&mut backoff).leave();
+        assert_eq!(0, backoff.0);
+
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (leave_tx, leave_rx) = mpsc::channel();
+        let crit = critical.clone();
+        let handle = thread::spawn(move || {
+            let entered = crit.enter_unchecked();
+            entered_tx.send(()).unwrap();
+            leave_rx.recv().unwrap();
+            entered.leave();
+        });
+        entered_rx.recv().unwrap();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let mut backoff = Counting(0);
+                critical.enter_with_backoff(&mut backoff).leave();
+                backoff.0
+            });
+            // Falls back to blocking once the backoff completes
+            while critical.approx_waiter_count() == 0 {
+                thread::yield_now();
+            }
+            leave_tx.send(()).unwrap();
+            assert_eq!(3, waiter.join().unwrap());
+        });
+        handle.join().unwrap();
+        let mut backoff = crate::ExponentialBackoff::new();
+        critical.enter_with_backoff(&mut backoff).leave();
+    }
+
+    #[test]
+    fn lockable() {
+        fn work<L: crate::Lockable>(lock: &L) -> bool {
+            let _entered = lock.enter();
+            lock.try_enter().is_some()
+        }
+        let critical = CriticalSection::new();
+        assert!(work(&critical));
+        assert!(!critical.is_locked());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn enter_timed() {
+        let critical = CriticalSection::new();
+        let (held_tx, held_rx) = mpsc::channel();
+        let entered = critical.enter_timed_with(move |held| held_tx.send(held).unwrap());
+        thread::sleep(Duration::from_millis(20));
+        assert!(entered.elapsed() >= Duration::from_millis(20));
+        assert_eq!(1, entered.recursion_count());
+        entered.leave();
+        assert!(held_rx.recv().unwrap() >= Duration::from_millis(20));
+
+        let held = std::cell::Cell::new(None);
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let _entered = critical.enter_timed_with(|d| held.set(Some(d)));
+            panic!("Still reported")
+        }))
+        .unwrap_err();
+        assert!(held.get().is_some());
+        assert!(!critical.is_locked());
+        critical.enter_timed().leave();
+    }
+
+    #[test]
+    fn named() {
+        let critical = CriticalSection::new_named("parser");
+        assert_eq!(Some("parser"), critical.name());
+        assert!(format!("{:?}", critical).ends_with(" \"parser\""));
+        assert_eq!(Some("parser"), critical.enter_unchecked().name());
+        let built = CriticalSection::builder().name("built").build().unwrap();
+        assert_eq!(Some("built"), built.name());
+        assert_eq!(None, CriticalSection::new().name());
+        #[cfg(feature = "deadlock-detection")]
+        {
+            let _built = built.enter_unchecked();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| critical.enter_unchecked()));
+            let message = result.unwrap_err();
+            let message = message.downcast_ref::<String>().unwrap();
+            assert!(message.contains("\"parser\"") && message.contains("\"built\""));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn recursion_overflow() {
+        use core::ptr::addr_of_mut;
+        let critical = CriticalSection::new();
+        let entered = critical.enter_unchecked();
+        // Entering i32::MAX times would take too long, so pretend it already happened.
+        let set_recursion = |count| {
+            entered.with_raw(|ptr| unsafe { addr_of_mut!((*ptr).RecursionCount).write(count) })
+        };
+        set_recursion(i32::MAX);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| critical.enter_unchecked()));
+        assert!(result.is_err());
+        assert_eq!(i32::MAX, entered.recursion_count());
+        set_recursion(1);
+        drop(entered);
+        assert!(critical.try_enter().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "CriticalStatic")]
+    fn in_place() {
+        let storage = Box::leak(Box::new(std::mem::MaybeUninit::uninit()));
+        let critical = CriticalSection::in_place(storage);
+        let entered = critical.enter();
+        assert!(critical.try_enter().is_some());
+        thread::spawn(move || assert!(critical.try_enter().is_none()))
+            .join()
+            .unwrap();
+        drop(entered);
+        assert!(!critical.is_locked());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn assert_held() {
+        use core::ptr::addr_of_mut;
+        let critical = CriticalSection::new();
+        let entered = critical.enter_unchecked();
+        entered.assert_held();
+        // Corrupt the fields assert_held() checks, then restore them before leaving.
+        let recursion =
+            entered.with_raw(|ptr| unsafe { addr_of_mut!((*ptr).RecursionCount).replace(0) });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| entered.assert_held()));
+        assert!(result.is_err());
+        entered.with_raw(|ptr| unsafe { addr_of_mut!((*ptr).RecursionCount).write(recursion) });
+        let owner = entered.with_raw(|ptr| unsafe {
+            addr_of_mut!((*ptr).OwningThread).replace(core::mem::zeroed())
+        });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| entered.assert_held()));
+        assert!(result.is_err());
+        entered.with_raw(|ptr| unsafe { addr_of_mut!((*ptr).OwningThread).write(owner) });
+        entered.assert_held();
+    }
+
+    #[test]
+    fn forget_guard() {
+        let critical = CriticalSection::new();
+        let token = critical.enter_unchecked().forget_guard();
+        let crit = critical.clone();
+        thread::spawn(move || assert!(crit.try_enter().is_none()))
+            .join()
+            .unwrap();
+        token.leave();
+        let crit = critical.clone();
+        thread::spawn(move || assert!(crit.try_enter().is_some()))
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn with_raw() {
+        use crate::raw::RawCriticalSection;
+        let critical = CriticalSection::new();
+        let entered = critical.enter_unchecked();
+        let recursion = entered.with_raw(|ptr| {
+            // Safety: the critical section is initialized and outlives the handle.
+            let raw = unsafe { RawCriticalSection::from_ptr(ptr) };
+            raw.enter();
+            let recursion = entered.recursion_count();
+            // Safety: entered just above, on this thread.
+            unsafe { raw.leave() };
+            recursion
+        });
+        assert_eq!(2, recursion);
+        assert_eq!(1, entered.recursion_count());
+    }
+
+    #[test]
+    fn probably_free() {
+        let critical = CriticalSection::new();
+        assert!(critical.probably_free());
+        let entered = critical.enter_unchecked();
+        // Recursive entry would succeed
+        assert!(critical.probably_free());
+        thread::scope(|s| {
+            s.spawn(|| assert!(!critical.probably_free()))
+                .join()
+                .unwrap();
+        });
+        entered.leave();
+        thread::scope(|s| {
+            s.spawn(|| assert!(critical.probably_free()))
+                .join()
+                .unwrap();
+        });
+    }
+
+    #[test]
+    // Keys hash by address, like the SectionId they are looked up with.
+    #[allow(clippy::mutable_key_type)]
+    fn section_id() {
+        use std::collections::HashMap;
+        let first = CriticalSection::new();
+        let second = CriticalSection::new();
+        let mut map = HashMap::new();
+        map.insert(first.clone(), 1);
+        map.insert(second.clone(), 2);
+        let entered = second.enter_unchecked();
+        assert_eq!(Some(&2), map.get(entered.id()));
+        assert_eq!(Some(&1), map.get(first.as_ref()));
+        let first_id: &crate::SectionId = first.as_ref();
+        let second_id: &crate::SectionId = second.as_ref();
+        assert_eq!(first.cmp(&second), first_id.cmp(second_id));
+    }
+
+    #[test]
+    fn with_spin_count_scope() {
+        let critical = CriticalSection::with_spin_count(100);
+        let result = critical.with_spin_count_scope(4000, || critical.spin_count());
+        assert_eq!(4000, result);
+        assert_eq!(100, critical.spin_count());
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            critical.with_spin_count_scope(4000, || panic!("Restore anyway"))
+        }))
+        .unwrap_err();
+        assert_eq!(100, critical.spin_count());
+    }
+
+    #[test]
+    #[cfg(all(feature = "std", not(feature = "no-poison")))]
+    fn poison_without_entering() {
+        let critical = CriticalSection::new();
+        assert!(!critical.is_poisoned());
+        let crit = critical.clone();
+        thread::spawn(move || {
+            let _entered = crit.enter_unchecked();
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        assert!(critical.is_poisoned());
+        critical.clear_poison();
+        assert!(!critical.is_poisoned());
+    }
+
+    #[test]
+    #[cfg(all(feature = "std", not(feature = "no-poison")))]
+    fn leave_checked() {
+        let critical = CriticalSection::new();
+        let outcome = critical.enter_unchecked().leave_checked();
+        assert_eq!(crate::LeaveOutcome { poisoned: false }, outcome);
+        let crit = critical.clone();
+        thread::spawn(move || {
+            let _entered = crit.enter_unchecked();
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        assert!(critical.enter_unchecked().leave_checked().poisoned);
+    }
+
+    #[test]
+    #[cfg(all(feature = "std", not(feature = "no-poison")))]
+    fn take_poison() {
+        let critical = CriticalSection::new();
+        assert!(!critical.enter_unchecked().take_poison());
+        let crit = critical.clone();
+        thread::spawn(move || {
+            let _entered = crit.enter_unchecked();
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        let entered = critical.enter_unchecked();
+        assert!(entered.take_poison());
+        assert!(!entered.is_poisoned());
+        assert!(!entered.take_poison());
+    }
+
+    #[test]
+    #[cfg(feature = "poison-result")]
+    fn enter_poison_result() {
+        let critical = CriticalSection::new();
+        assert!(critical.enter().is_ok());
+        let crit = critical.clone();
+        thread::spawn(move || {
+            let _entered = crit.enter();
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        let entered = match critical.enter() {
+            Ok(_) => panic!("Expected poison"),
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        entered.clear_poison();
+        entered.leave();
+        assert!(critical.enter().is_ok());
+    }
+
+    #[test]
+    fn bump() {
+        let critical = CriticalSection::new();
+        let crit = critical.clone();
+        thread::spawn(move || {
+            let _entered = crit.enter_unchecked();
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        let mut entered = critical.enter_unchecked();
+        entered.bump();
+        assert!(critical.is_locked());
+        assert_eq!(1, entered.recursion_count());
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
+        assert!(entered.is_poisoned());
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let critical = CriticalSection::new();
+        let ptr = critical.clone().into_raw();
+        assert_eq!(critical.as_raw(), ptr);
+        let restored = unsafe { CriticalSection::from_raw(ptr) };
+        assert_eq!(critical, restored);
+        drop(critical);
+        assert!(!restored.enter_unchecked().is_poisoned());
+    }
+
+    #[test]
+    fn builder() {
+        let critical = CriticalSectionBuilder::new()
+            .spin_count(4000)
+            .no_debug_info(true)
+            .build()
+            .unwrap();
+        assert_eq!(4000, critical.spin_count());
+        assert!(!critical.enter_unchecked().is_poisoned());
+        let critical = CriticalSection::builder()
+            .dynamic_spin(true)
+            .build()
+            .unwrap();
+        assert!(critical.try_enter().is_some());
+    }
+
+    #[test]
+    // Hash and Ord only look at the address, never the interior-mutable contents.
+    #[allow(clippy::mutable_key_type)]
+    fn hash_ord_identity() {
+        fn hash(critical: &CriticalSection) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            critical.hash(&mut hasher);
+            hasher.finish()
+        }
+        let a = CriticalSection::new();
+        let b = CriticalSection::new();
+        assert_eq!(hash(&a), hash(&a.clone()));
+        assert_eq!(a.cmp(&a.clone()), std::cmp::Ordering::Equal);
+        assert_ne!(a, b);
+        assert_eq!(a < b, a.as_raw() < b.as_raw());
+        let set: BTreeSet<_> = vec![b.clone(), a.clone(), b.clone(), a.clone()]
+            .into_iter()
+            .collect();
+        assert_eq!(2, set.len());
+        let sorted: Vec<_> = set.into_iter().collect();
+        assert!(sorted[0] < sorted[1]);
+        assert_eq!(sorted, {
+            let mut v = vec![b, a];
+            v.sort();
+            v
+        });
+    }
+
+    #[test]
+    #[cfg(all(feature = "metrics", feature = "std"))]
+    fn stats() {
+        let critical = CriticalSection::new();
+        assert_eq!(crate::CriticalStats::default(), critical.stats());
+        let entered = critical.enter_unchecked();
+        let crit = critical.clone();
+        thread::spawn(move || {
+            assert!(crit.try_enter().is_none());
+            assert!(crit.try_enter_for(Duration::from_millis(10)).is_none());
+        })
+        .join()
+        .unwrap();
+        entered.leave();
+        assert!(critical.try_enter().is_some());
+        let stats = critical.stats();
+        assert_eq!(2, stats.enters);
+        assert!(stats.try_enter_failures >= 2);
+        assert!(stats.spin_fallbacks >= 1);
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic)]
+    fn non_reentrant() {
+        let critical = CriticalSection::new_non_reentrant();
+        let _outer = critical.enter_unchecked();
+        let crit = critical.clone();
+        thread::spawn(move || assert!(crit.try_enter().is_none()))
+            .join()
+            .unwrap();
+        let _inner = critical.enter_unchecked();
+    }
+
+    #[test]
+    fn into_send() {
+        let critical = CriticalSection::new();
+        let entered = unsafe { critical.enter_unchecked().into_send() };
+        let crit = critical.clone();
+        let entered = thread::scope(|s| {
+            s.spawn(move || {
+                assert!(crit.try_enter().is_none());
+                entered
+            })
+            .join()
+            .unwrap()
+        });
+        assert!(critical.is_locked());
+        entered.leave();
+        assert!(!critical.is_locked());
+    }
+
+    #[test]
+    #[cfg(all(feature = "debug-thread-check", debug_assertions))]
+    fn leave_on_wrong_thread() {
+        use crate::raw::RawCriticalSection;
+        let critical = CriticalSection::new();
+        let entered = unsafe { critical.enter_unchecked().into_send() };
+        thread::scope(|s| s.spawn(move || entered.leave()).join().unwrap_err());
+        // The check panicked without leaving, so this thread still holds it.
+        assert!(critical.held_by_current_thread());
+        unsafe { RawCriticalSection::from_ptr(critical.as_raw()).leave() }
+        assert!(!critical.is_locked());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn abort_on_poison() {
+        const CHILD: &str = "CRITIRS_ABORT_ON_POISON_CHILD";
+        if std::env::var_os(CHILD).is_some() {
+            let critical = CriticalSectionBuilder::new()
+                .on_poison(crate::PoisonPolicy::Abort)
+                .build()
+                .unwrap();
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                let _entered = critical.enter_unchecked();
+                panic!("Abort")
+            }));
+            // Unreachable if the guard aborted while unwinding
+            std::process::exit(0);
+        }
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "crit::tests::abort_on_poison", "--nocapture"])
+            .env(CHILD, "1")
+            .status()
+            .unwrap();
+        assert!(!status.success());
+        // 101 means the test harness saw an ordinary panic
+        assert_ne!(Some(101), status.code());
+    }
+
+    #[test]
+    fn fair() {
+        static mut X: usize = 0;
+        let critical = CriticalSection::new_fair();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let crit = critical.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let mut entered = crit.enter_unchecked();
+                        let inner = crit.enter_unchecked();
+                        let x = 1 + unsafe { X };
+                        inner.leave();
+                        unsafe { X = x };
+                        entered.bump();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(8000, unsafe { X });
+        let entered = critical.try_enter().unwrap();
+        let crit = critical.clone();
+        thread::spawn(move || assert!(crit.try_enter().is_none()))
+            .join()
+            .unwrap();
+        entered.leave();
+        assert!(critical.try_enter().is_some());
+    }
+
+    #[test]
+    fn leak() {
+        static mut X: usize = 0;
+        let critical: &'static CriticalSection = CriticalSection::new().leak();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let _entered = critical.enter_unchecked();
+                    unsafe { X += 1 };
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(8, unsafe { X });
+    }
+
+    #[test]
+    #[cfg(all(feature = "deadlock-detection", debug_assertions))]
+    fn lock_order() {
+        let first = CriticalSection::new();
+        let second = CriticalSection::new();
+        let ignored = CriticalSectionBuilder::new()
+            .ignore_lock_order(true)
+            .build()
+            .unwrap();
+        {
+            let _second = second.enter_unchecked();
+            let _ignored = ignored.enter_unchecked();
+            let _second_again = second.enter_unchecked();
+        }
+        let _first = first.enter_unchecked();
+        let _second = second.enter_unchecked();
+        drop(_first);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| first.enter_unchecked().leave()));
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("Lock order violation"));
+        // Panicking before entering neither enters nor poisons
+        assert!(!first.is_locked());
+        assert!(!first.is_poisoned());
+    }
+
+    #[test]
+    fn try_enter_spinning() {
+        let critical = CriticalSection::new();
+        assert!(critical.try_enter_spinning(0).is_some());
+        let entered = critical.enter_unchecked();
+        let crit = critical.clone();
+        thread::spawn(move || assert!(crit.try_enter_spinning(1000).is_none()))
+            .join()
+            .unwrap();
+        entered.leave();
+        assert!(critical.try_enter_spinning(1000).is_some());
+        // The pre-check does not skip recursive entry
+        let entered = critical.enter_unchecked();
+        assert!(critical.try_enter_spinning(1000).is_some());
+        entered.leave();
+    }
+
+    #[test]
+    fn try_enter_distinct() {
+        let critical = CriticalSection::new();
+        let outer = match critical.try_enter_distinct() {
+            TryEnterStatus::Acquired(entered) => entered,
+            status => panic!("Expected Acquired, got {:?}", status),
+        };
+        assert!(matches!(
+            critical.try_enter_distinct(),
+            TryEnterStatus::Reentered(_)
+        ));
+        let crit = critical.clone();
+        thread::spawn(move || assert!(matches!(crit.try_enter_distinct(), TryEnterStatus::Busy)))
+            .join()
+            .unwrap();
+        outer.leave();
+        assert!(matches!(
+            critical.try_enter_distinct(),
+            TryEnterStatus::Acquired(_)
+        ));
+    }
+
+    #[test]
+    fn try_enter_budget() {
+        let critical = CriticalSection::new();
+        let result = critical.try_enter_budget(100);
+        assert_eq!(0, result.spins);
+        let entered = result.entered.unwrap();
+        let crit = critical.clone();
+        let result = thread::spawn(move || {
+            let result = crit.try_enter_budget(100);
+            (result.entered.is_some(), result.spins)
+        })
+        .join()
+        .unwrap();
+        assert_eq!((false, 100), result);
+        #[cfg(feature = "metrics")]
+        assert_eq!(1, critical.stats().try_enter_failures);
+        entered.leave();
+        let result = critical.try_enter_budget(0);
+        assert!(result.entered.is_some());
+        assert_eq!(0, result.spins);
+    }
+
+    #[test]
+    fn effective_spin_count() {
+        let critical = CriticalSection::with_spin_count(100);
+        // Windows decides from the same count, not the process's affinity mask.
+        let expected = if crate::wrapper::processor_count() > 1 {
+            100
+        } else {
+            0
+        };
+        assert_eq!(expected, critical.effective_spin_count());
+    }
+
+    #[test]
+    fn adjust_spin_count() {
+        let critical = CriticalSection::with_spin_count(100);
+        let entered = critical.enter_unchecked();
+        // Single-processor systems store a spin count of 0, so there is nothing to double.
+        let expected = if crate::wrapper::processor_count() > 1 {
+            200
+        } else {
+            0
+        };
+        assert_eq!(
+            expected,
+            entered.adjust_spin_count(|spin_count| spin_count * 2)
+        );
+        assert_eq!(expected, critical.spin_count());
+        assert_eq!(
+            SpinCount::MAX.get(),
+            entered.adjust_spin_count(|_| u32::MAX)
+        );
+    }
+
+    #[test]
+    fn spin_count_clamped() {
+        assert_eq!(4000, SpinCount::from(4000).get());
+        assert_eq!(SpinCount::MAX, SpinCount::from(0x8000_0FA0));
+        let critical = CriticalSection::with_spin_count(SpinCount::new(u32::MAX));
+        assert_eq!(SpinCount::MAX.get(), critical.spin_count());
+        critical.set_spin_count(0x8000_0FA0);
+        assert_eq!(SpinCount::MAX.get(), critical.spin_count());
+    }
+
+    #[test]
+    fn enter_owned() {
+        struct Holder {
+            entered: Option<OwnedEnteredCritical>,
+        }
+        let critical = CriticalSection::new();
+        let mut holder = Holder {
+            entered: Some(critical.clone().enter_owned()),
+        };
+        assert!(critical.is_locked());
+        assert_eq!(1, holder.entered.as_ref().unwrap().recursion_count());
+        // The guard keeps the section alive
+        drop(critical);
+        holder.entered.take().unwrap().leave();
+        let critical = CriticalSection::new();
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
+        {
+            let crit = critical.clone();
+            thread::spawn(move || {
+                let _entered = crit.enter_owned();
+                panic!("Poison it")
+            })
+            .join()
+            .unwrap_err();
+            assert!(critical.enter_owned().is_poisoned());
+        }
+        assert!(!critical.is_locked());
+    }
+
+    #[test]
+    fn wait_until_unlocked() {
+        let critical = CriticalSection::new();
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let crit = critical.clone();
+        let handle = thread::spawn(move || {
+            let _entered = crit.enter_unchecked();
+            entered_tx.send(()).unwrap();
+            thread::sleep(std::time::Duration::from_millis(50));
+        });
+        entered_rx.recv().unwrap();
+        critical.wait_until_unlocked();
+        assert!(!critical.is_locked());
+        handle.join().unwrap();
+        let critical = CriticalSection::new_non_reentrant();
+        let entered = critical.enter_unchecked();
+        critical.wait_until_unlocked();
+        assert_eq!(1, entered.recursion_count());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn lock_async() {
+        use std::{
+            future::Future,
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+            task::{Context, Poll, Wake, Waker},
+        };
+        struct CountWakes(AtomicUsize);
+        impl Wake for CountWakes {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let critical = CriticalSection::new();
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (leave_tx, leave_rx) = mpsc::channel::<()>();
+        let crit = critical.clone();
+        let handle = thread::spawn(move || {
+            let _entered = crit.enter_unchecked();
+            entered_tx.send(()).unwrap();
+            leave_rx.recv().unwrap();
+        });
+        entered_rx.recv().unwrap();
+        let mut future = Box::pin(critical.lock_async());
+        for _ in 0..3 {
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+        }
+        assert!(wakes.0.load(Ordering::Relaxed) > 0);
+        leave_tx.send(()).unwrap();
+        handle.join().unwrap();
+        let entered = loop {
+            if let Poll::Ready(entered) = future.as_mut().poll(&mut cx) {
+                break entered;
+            }
+        };
+        assert_eq!(1, entered.recursion_count());
+        drop(entered);
+        let mut future = Box::pin(critical.lock_async_with(|| async {}));
+        assert!(future.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn from_config() {
+        let config = crate::CriticalSectionConfig {
+            spin_count: Some(u32::MAX),
+            dynamic_spin: true,
+            ..Default::default()
+        };
+        let critical = config.build().unwrap();
+        assert_eq!(SpinCount::MAX.get(), critical.spin_count());
+        let critical = CriticalSectionBuilder::from(crate::CriticalSectionConfig::default())
+            .build()
+            .unwrap();
+        assert_eq!(0, critical.spin_count());
+    }
+
+    #[test]
+    fn section() {
+        let critical = CriticalSection::with_spin_count(42);
+        let entered = critical.enter_unchecked();
+        let section = entered.section().unwrap();
+        assert_eq!(critical, section);
+        assert_eq!(42, section.spin_count());
+        drop(entered);
+        drop(critical);
+        // The recovered handle keeps the section alive by itself
+        assert!(section.try_enter().is_some());
+    }
+
+    #[test]
+    fn waiter_count() {
+        let critical = CriticalSection::new();
+        assert_eq!(-1, critical.raw_lock_count());
+        assert_eq!(0, critical.approx_waiter_count());
+        let entered = critical.enter_unchecked();
+        assert_eq!(0, critical.approx_waiter_count());
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let crit = critical.clone();
+                thread::spawn(move || crit.wait_until_unlocked())
+            })
+            .collect();
+        while critical.approx_waiter_count() < 2 {
+            thread::yield_now();
+        }
+        entered.leave();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(0, critical.approx_waiter_count());
+    }
+
+    #[test]
+    fn held_by_current_thread() {
+        let critical = CriticalSection::new();
+        assert!(!critical.held_by_current_thread());
+        let entered = critical.enter_unchecked();
+        assert!(critical.held_by_current_thread());
+        let crit = critical.clone();
+        thread::spawn(move || assert!(!crit.held_by_current_thread()))
+            .join()
+            .unwrap();
+        entered.leave();
+        assert!(!critical.held_by_current_thread());
+    }
+
+    #[test]
+    fn enter_all() {
+        let sections: Vec<_> = (0..4).map(|_| CriticalSection::new()).collect();
+        let reversed: Vec<_> = sections.iter().rev().cloned().collect();
+        let handles: Vec<_> = vec![sections.clone(), reversed]
+            .into_iter()
+            .map(|sections| {
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let _entered = CriticalSection::enter_all(&sections);
+                        thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let entered = CriticalSection::enter_all(&sections);
+        for (entered, critical) in entered.iter().zip(&sections) {
+            assert_eq!(Some(critical.clone()), entered.section());
+        }
+        let [first, last] = CriticalSection::enter_array([&sections[0], &sections[3]]);
+        assert_eq!(2, first.recursion_count());
+        drop((entered, first, last));
+
+        let held = sections[2].enter_unchecked();
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert!(CriticalSection::try_enter_all(&sections).is_none());
+                assert!(CriticalSection::try_enter_array([&sections[3], &sections[2]]).is_none());
+                // Everything entered before the failure was left again
+                assert!(sections
+                    .iter()
+                    .all(|critical| !critical.held_by_current_thread()));
+            })
+            .join()
+            .unwrap();
+        });
+        drop(held);
+        assert!(CriticalSection::try_enter_all(&sections).is_some());
+    }
+
+    #[test]
+    fn enter_both() {
+        let a = CriticalSection::new();
+        let b = CriticalSection::new();
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let (a, b) = (a.clone(), b.clone());
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        // Alternate argument order, which would deadlock without ordering
+                        let (x, y) = if i % 2 == 0 { (&a, &b) } else { (&b, &a) };
+                        let _entered = x.enter_both(y);
+                        thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let (entered_a, entered_b) = a.enter_both(&a);
+        assert_eq!(2, entered_a.recursion_count());
+        drop((entered_a, entered_b));
+
+        let entered_b = b.enter_unchecked();
+        let crit_a = a.clone();
+        let crit_b = b.clone();
+        thread::spawn(move || {
+            assert!(crit_a.try_enter_both(&crit_b).is_none());
+            assert!(crit_b.try_enter_both(&crit_a).is_none());
+            // Whichever was tried first must have been left again
+            assert!(!crit_a.is_locked());
+        })
+        .join()
+        .unwrap();
+        drop(entered_b);
+        assert!(b.try_enter_both(&a).is_some());
+    }
+
+    #[test]
+    fn reinit() {
+        let mut critical = CriticalSection::with_spin_count(4000);
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
+        {
+            let crit = critical.clone();
+            thread::spawn(move || {
+                let _entered = crit.enter_unchecked();
+                panic!("Poison it")
+            })
+            .join()
+            .unwrap_err();
+            assert!(critical.is_poisoned());
+        }
+        let clone = critical.clone();
+        assert_eq!(Err(crate::wrapper::ERROR_BUSY), critical.reinit());
+        drop(clone);
+        assert_eq!(Ok(()), critical.reinit());
+        assert!(!critical.is_poisoned());
+        assert_eq!(4000, critical.spin_count());
+        assert!(critical.try_enter().is_some());
+    }
+
+    #[test]
+    fn try_delete() {
+        use crate::raw::RawCriticalSection;
+        let critical = CriticalSection::new();
+        let clone = critical.clone();
+        let critical = critical.try_delete().unwrap_err();
+        drop(clone);
+        // Forgotten, so it is still held once the borrow ends.
+        std::mem::forget(critical.enter_unchecked());
+        let critical = critical.try_delete().unwrap_err();
+        // Safety: entered above on this thread, and not yet left.
+        unsafe { RawCriticalSection::from_ptr(critical.lpCriticalSection()).leave() };
+        assert!(critical.try_delete().is_ok());
+    }
+
+    #[test]
+    fn drop_races() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static DELETES: AtomicUsize = AtomicUsize::new(0);
+        let mut critical = CriticalSection::new();
+        Arc::get_mut(&mut critical.inner).unwrap().deletes = Some(&DELETES);
+        let weak = Arc::downgrade(&critical.inner);
+        let barrier = Arc::new(Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (crit, barrier) = (critical.clone(), barrier.clone());
+                thread::spawn(move || {
+                    let clones: Vec<_> = (0..100).map(|_| crit.clone()).collect();
+                    barrier.wait();
+                    drop(clones);
+                    drop(crit);
+                })
+            })
+            .collect();
+        drop(critical);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // The last reference dropped the SectionInner, deleting it exactly
+        // once, even though a weak reference exists.
+        assert!(weak.upgrade().is_none());
+        assert_eq!(1, DELETES.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn lazy() {
+        static LAZY: LazyCriticalSection = LazyCriticalSection::new();
+        static mut X: usize = 0;
+        assert_eq!("LazyCriticalSection: uninitialized", format!("{:?}", LAZY));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(|| {
+                    let _entered = LAZY.enter_unchecked();
+                    unsafe { X += 1 };
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(8, unsafe { X });
+        let critical = LAZY.clone();
+        assert_eq!(*LAZY.get(), critical);
+        assert!(critical.try_enter().is_some());
+    }
 }
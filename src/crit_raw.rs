@@ -0,0 +1,228 @@
+use crate::common::{PoisonableCriticalSection, POISONABLE_ZEROED};
+
+use crate::wrapper::{delete_cs, enter_cs, init_cs, leave_cs, try_enter_cs};
+
+#[cfg(feature = "std")]
+use crate::common::SPINS_BEFORE_YIELD;
+#[cfg(feature = "std")]
+use lock_api::RawMutexTimed;
+use lock_api::{GuardNoSend, RawMutex};
+
+#[cfg(feature = "std")]
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use core::hint;
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+/// CritRaw is a critical section implementing `lock_api::RawMutex`, so it can be used as the
+/// backend for `lock_api::Mutex` and related types.
+///
+/// The critical section is heap-allocated and initialized on first use. This allows the
+/// const `INIT` required by lock_api, while ensuring the CRITICAL_SECTION is never moved in
+/// memory once initialized, even if the containing lock is.
+///
+/// Critical sections are recursive, so locking recursively will not deadlock as it would with
+/// a typical RawMutex. However, lock_api relies on locks being exclusive, so re-entering from
+/// the owning thread panics in `lock()` and fails in `try_lock()`.
+pub struct CritRaw {
+    inner: AtomicPtr<PoisonableCriticalSection>,
+}
+
+/// A lock_api::Mutex backed by CritRaw, usable wherever lock_api mutexes are expected.
+pub type CriticalMutex<T> = lock_api::Mutex<CritRaw, T>;
+/// The guard for a lock_api CriticalMutex.
+pub type CriticalMutexGuard<'a, T> = lock_api::MutexGuard<'a, CritRaw, T>;
+
+impl CritRaw {
+    // Returns whether the critical section was entered, or None if the current thread
+    // already holds it and so will never succeed.
+    fn try_enter(&self) -> Option<bool> {
+        let inner = self.get();
+        // Safety: returns non-zero if we are in critical section when call returns.
+        // Naturally thread-safe.
+        if 0 == unsafe { try_enter_cs(inner.critical.get()) } {
+            return Some(false);
+        }
+        if inner.recursion_count() > 1 {
+            // Safety: we entered above, leave exactly once.
+            unsafe { leave_cs(inner.critical.get()) }
+            return None;
+        }
+        Some(true)
+    }
+    fn get(&self) -> &PoisonableCriticalSection {
+        let existing = self.inner.load(Ordering::Acquire);
+        if !existing.is_null() {
+            // Safety: once set, inner is never changed or freed until drop
+            return unsafe { &*existing };
+        }
+        let new = Box::into_raw(Box::new(POISONABLE_ZEROED));
+        // Safety: new is a brand new CRITICAL_SECTION object that will not be moved in memory.
+        let result = unsafe { init_cs((*new).critical.get()) };
+        if result.is_err() {
+            // Safety: new was never shared
+            drop(unsafe { Box::from_raw(new) });
+        }
+        result.expect("Failed to initialize critical section");
+        match self
+            .inner
+            .compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire)
+        {
+            // Safety: new is now owned by self, and is never changed or freed until drop
+            Ok(_) => unsafe { &*new },
+            Err(existing) => {
+                // Safety: another thread won the race, and new was never shared
+                unsafe {
+                    delete_cs((*new).critical.get());
+                    drop(Box::from_raw(new));
+                    &*existing
+                }
+            }
+        }
+    }
+}
+
+unsafe impl RawMutex for CritRaw {
+    const INIT: Self = Self {
+        inner: AtomicPtr::new(ptr::null_mut()),
+    };
+    // Safety: it is not okay to enter from one thread and leave from another.
+    type GuardMarker = GuardNoSend;
+
+    fn lock(&self) {
+        let inner = self.get();
+        // Safety: no return value. Naturally thread-safe.
+        unsafe { enter_cs(inner.critical.get()) }
+        if inner.recursion_count() > 1 {
+            // Safety: we entered above, leave exactly once.
+            unsafe { leave_cs(inner.critical.get()) }
+            panic!("CritRaw is already locked by the current thread")
+        }
+    }
+    fn try_lock(&self) -> bool {
+        self.try_enter() == Some(true)
+    }
+    unsafe fn unlock(&self) {
+        // Safety: caller guarantees we are entered on this thread.
+        leave_cs(self.get().critical.get())
+    }
+    fn is_locked(&self) -> bool {
+        let existing = self.inner.load(Ordering::Acquire);
+        // Safety: once set, inner is never changed or freed until drop
+        !existing.is_null() && unsafe { &*existing }.is_locked()
+    }
+}
+
+// Like CriticalSection::try_enter_for(), this polls, so it is only suitable for coarse timeouts.
+#[cfg(feature = "std")]
+unsafe impl RawMutexTimed for CritRaw {
+    type Duration = Duration;
+    type Instant = Instant;
+
+    fn try_lock_for(&self, timeout: Duration) -> bool {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            None => {
+                self.lock();
+                true
+            }
+        }
+    }
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        let mut spins = 0;
+        loop {
+            match self.try_enter() {
+                Some(true) => return true,
+                None => return false,
+                Some(false) if Instant::now() >= deadline => return false,
+                Some(false) if spins < SPINS_BEFORE_YIELD => {
+                    spins += 1;
+                    hint::spin_loop();
+                }
+                Some(false) => thread::yield_now(),
+            }
+        }
+    }
+}
+
+impl Drop for CritRaw {
+    fn drop(&mut self) {
+        let inner = *self.inner.get_mut();
+        if !inner.is_null() {
+            // Safety: we have exclusive access, so no thread can be entered or waiting.
+            unsafe {
+                delete_cs((*inner).critical.get());
+                drop(Box::from_raw(inner));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CritRaw;
+    use lock_api::Mutex;
+    #[cfg(feature = "std")]
+    use std::time::{Duration, Instant};
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn threads_on_the_wall() {
+        let mut handles = Vec::with_capacity(99);
+        let mutex = Arc::new(Mutex::<CritRaw, usize>::new(0));
+        for _ in 0..99 {
+            let mutex = mutex.clone();
+            handles.push(thread::spawn(move || {
+                let mut x = mutex.lock();
+                let next = 1 + *x;
+                thread::yield_now();
+                *x = next;
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(99, *mutex.lock());
+    }
+
+    #[test]
+    fn relock_fails() {
+        let mutex = Mutex::<CritRaw, ()>::new(());
+        let _outer = mutex.lock();
+        assert!(mutex.is_locked());
+        assert!(mutex.try_lock().is_none());
+    }
+
+    #[test]
+    fn aliases() {
+        let mutex = crate::lock_api::CriticalMutex::new(0u32);
+        let mut guard: crate::lock_api::CriticalMutexGuard<'_, u32> = mutex.lock();
+        *guard += 1;
+        drop(guard);
+        assert_eq!(1, mutex.into_inner());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn try_lock_for() {
+        let mutex = Arc::new(Mutex::<CritRaw, ()>::new(()));
+        let guard = mutex.lock();
+        assert!(mutex.try_lock_for(Duration::from_millis(10)).is_none());
+        let m = mutex.clone();
+        let start = Instant::now();
+        thread::spawn(move || assert!(m.try_lock_for(Duration::from_millis(50)).is_none()))
+            .join()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        drop(guard);
+        assert!(mutex.try_lock_for(Duration::from_millis(10)).is_some());
+    }
+}
@@ -6,21 +6,43 @@
 // By not using std, we are certain to not allocate.
 
 use crate::common::{PoisonableCriticalSection, POISONABLE_ZEROED};
-use crate::EnteredCritical;
+#[cfg(not(feature = "spin-fallback"))]
+use crate::lazy_init::POISONED;
+use crate::lazy_init::{run_init_once, INITIALIZED, INITIALIZING, UNINITIALIZED};
+#[cfg(feature = "metrics")]
+use crate::CriticalStats;
+use crate::{CriticalSectionConfig, EnteredCritical, Lockable, SectionId, SpinCount};
+
+use static_assertions::{assert_impl_all, assert_not_impl_any};
 
 use crate::wrapper::{
-    /* delete_cs, */ enter_cs, init_cs, init_cs_with_spin_count, set_cs_spin_count,
-    try_enter_cs,
+    delete_cs, init_cs, init_cs_ex, init_cs_with_spin_count, CRITICAL_SECTION, CRIT_ZEROED,
+    ERROR_ALREADY_INITIALIZED,
 };
 
-use winapi::um::minwinbase::CRITICAL_SECTION;
-
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    borrow::Borrow,
+    cell::UnsafeCell,
+    fmt::{self, Formatter},
+    hash::{Hash, Hasher},
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-const UNINITIALIZED: usize = 0;
-const INITIALIZING: usize = 1;
-const INITIALIZED: usize = 2;
-const POISONED: usize = 3;
+/// The lazy initialization state of a CriticalStatic, from CriticalStatic::init_state().
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InitState {
+    /// Not yet initialized. It will be on first use.
+    Uninitialized,
+    /// Another thread is initializing it right now.
+    Initializing,
+    /// Initialized and ready to use.
+    Initialized,
+    /// Initialization failed, and it can never be used. This is unrelated to a thread
+    /// panicking while inside it, which is reported by is_poisoned().
+    Poisoned,
+}
 
 /// CriticalStatic is a CriticalStatic primitive that can be contructed statically and safely used.
 /// Deleting a CriticalStatic is unsafe, and you must either ensure it gets re-initialized prior to
@@ -29,6 +51,11 @@ const POISONED: usize = 3;
 ///
 /// Calling get_ref() will return a value that can bypass an initialization check for all
 /// operations.
+///
+/// With the spin-fallback feature, if the OS fails to initialize the critical section, it
+/// falls back to an internal spin lock instead of panicking. This is a degraded mode: the
+/// spin lock is not recursive, so entering again on the same thread deadlocks, it burns CPU
+/// while waiting, and it cannot be used with CriticalCondvar. Check is_fallback() to detect it.
 pub struct CriticalStatic {
     // init_spin_count Safety: this is only set during contruction and never modified.
     // Therefore, no concern about thread-safety.
@@ -38,17 +65,17 @@ pub struct CriticalStatic {
     inner: PoisonableCriticalSection,
 }
 
-// Safety note:
-// CriticalStaticRef<Init> can be copied freely.
-// CriticalStaticRef<Uninit> may not be copied or cloned.
-// This allows CriticalStaticRef::<Uninit>::init() to be safe.
+/// A thin reference to a CriticalStatic, obtained from CriticalStatic::get_ref().
+///
+/// CriticalStaticRef<Init> is Send, Sync and Copy, so it may be captured by any number of
+/// threads, but the EnteredCritical it returns stays on the thread which entered.
 #[derive(Copy, Clone)]
 pub struct CriticalStaticRef<State>(&'static PoisonableCriticalSection, State);
 #[derive(Copy, Clone)]
 pub struct Init;
-// For now, all CriticalStaticRef<Uninit> is removed. Need to think about
-// Init -> Uninit -> Init flow and ensure there is a correct way to do this.
-// pub struct Uninit;
+
+assert_impl_all!(CriticalStaticRef<Init>: Send, Sync, Copy);
+assert_not_impl_any!(EnteredCritical<'static>: Send, Sync);
 
 // Safety: Send and Sync are safe becuase these types work with &'static CRITICAL_SECTION.
 unsafe impl Sync for CriticalStatic {}
@@ -65,52 +92,44 @@ impl CriticalStatic {
         }
     }
     /// Creates a new CriticalStatic which will be initialized with the provided spin_count.
+    /// Being a const fn, this takes a u32, which is clamped as by SpinCount::new().
     pub const fn with_spin_count(spin_count: u32) -> Self {
+        let spin_count = SpinCount::new(spin_count).get();
         Self {
             init_spin_count: Some(spin_count),
             init: AtomicUsize::new(UNINITIALIZED),
             inner: POISONABLE_ZEROED,
         }
     }
+    /// Gives the CriticalStatic a name, which is shown in its Debug output and tracing
+    /// events. Being a const fn, this can be chained onto new() or with_spin_count() in a
+    /// static.
+    pub const fn with_name(mut self, name: &'static str) -> Self {
+        self.inner.name = Some(name);
+        self
+    }
+    /// Returns the name given by with_name(), if any.
+    pub fn name(&'static self) -> Option<&'static str> {
+        self.inner.name
+    }
     fn init_once(&'static self) {
-        struct PoisonCatcher<'a>(&'a AtomicUsize);
-        impl Drop for PoisonCatcher<'_> {
-            fn drop(&mut self) {
-                self.0.store(POISONED, Ordering::Relaxed)
-            }
-        }
-        if INITIALIZED == self.init.load(Ordering::Acquire) {
-            return;
-        } else if self
-            .init
-            .compare_exchange(
-                UNINITIALIZED,
-                INITIALIZING,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            )
-            .is_ok()
-        {
-            let catcher = PoisonCatcher(&self.init);
-            if let Some(spin_count) = self.init_spin_count {
-                unsafe { init_cs_with_spin_count(self.lpCriticalSection(), spin_count) }
+        let ptr = self.lpCriticalSection();
+        let spin_count = self.init_spin_count;
+        run_init_once(&self.init, || {
+            // Safety: run_init_once() calls this at most once, before anything else uses it.
+            let result = if let Some(spin_count) = spin_count {
+                unsafe { init_cs_with_spin_count(ptr, spin_count) }
             } else {
-                unsafe { init_cs(self.lpCriticalSection()) }
+                unsafe { init_cs(ptr) }
+            };
+            // Nothing else can use it until run_init_once() marks it initialized.
+            #[cfg(feature = "spin-fallback")]
+            if result.is_err() {
+                self.inner.set_fallback(true);
+                return Ok(());
             }
-            core::mem::forget(catcher);
-            self.init.store(INITIALIZED, Ordering::Release);
-            return;
-        } else {
-            // It won't take long, just spin
-            loop {
-                let status = self.init.load(Ordering::Acquire);
-                if INITIALIZED == status {
-                    return;
-                } else if POISONED == status {
-                    panic!("Critical Section init failed")
-                }
-            }
-        }
+            result
+        })
     }
     #[allow(non_snake_case)]
     fn lpCriticalSection(&'static self) -> *mut CRITICAL_SECTION {
@@ -120,9 +139,9 @@ impl CriticalStatic {
     /// calling thread is already in the Critical Section.
     pub fn enter(&'static self) -> EnteredCritical<'static> {
         self.init_once();
-        // Safety: might panic, no return value. Naturally thread-safe.
+        // Safety: no return value. Naturally thread-safe.
         unsafe {
-            enter_cs(self.lpCriticalSection());
+            self.inner.enter_raw();
             EnteredCritical::new(&self.inner)
         }
     }
@@ -131,21 +150,86 @@ impl CriticalStatic {
     /// Section.
     pub fn try_enter(&'static self) -> Option<EnteredCritical<'static>> {
         self.init_once();
-        // Safety: returns non-zero if we are in critical section when call returns.
+        // Safety: returns true if we are in critical section when call returns.
         // Naturally thread-safe.
         unsafe {
-            match try_enter_cs(self.lpCriticalSection()) {
-                0 => None,
-                _ => Some(EnteredCritical::new(&self.inner)),
+            if self.inner.try_enter_raw() {
+                Some(EnteredCritical::new(&self.inner))
+            } else {
+                self.inner.record_try_enter_failure();
+                None
             }
         }
     }
     /// Sets the spin count of this Critical Section, and returns the
     /// old value
-    pub fn set_spin_count(&'static self, spin_count: u32) -> u32 {
+    pub fn set_spin_count(&'static self, spin_count: impl Into<SpinCount>) -> u32 {
         self.init_once();
-        // Safety: cannot fail. Returns previous spin_count. Naturally thread-safe.
-        unsafe { set_cs_spin_count(self.lpCriticalSection(), spin_count) }
+        self.inner.set_spin_count(spin_count.into())
+    }
+    /// Returns the current spin count of this Critical Section
+    pub fn spin_count(&'static self) -> u32 {
+        self.init_once();
+        self.inner.spin_count()
+    }
+    /// Returns true if a thread panicked while inside this Critical Section. This does not
+    /// enter or initialize it, so the result may be stale as soon as it is returned.
+    pub fn is_poisoned(&'static self) -> bool {
+        self.inner.is_poisoned()
+    }
+    /// Returns the id of the thread which currently owns this Critical Section, or None if
+    /// it is not held. This is only a snapshot intended for debugging.
+    pub fn owning_thread_id(&'static self) -> Option<u32> {
+        self.inner.owning_thread_id()
+    }
+    /// Returns true if any thread currently holds this Critical Section, without
+    /// attempting to enter it. This is a best-effort snapshot which may be stale as soon
+    /// as it is returned, and is intended for monitoring rather than synchronization.
+    /// Returns false if it is not initialized.
+    pub fn is_locked(&'static self) -> bool {
+        // An uninitialized CRITICAL_SECTION reads as held, so check the init state first.
+        // In spin-fallback mode it is also INITIALIZED, and is_locked() checks the spin lock.
+        INITIALIZED == self.init.load(Ordering::Acquire) && self.inner.is_locked()
+    }
+    /// Returns a snapshot of the counters kept for this Critical Section. The counters are
+    /// plain atomics stored inline, so no allocation is involved.
+    #[cfg(feature = "metrics")]
+    pub fn stats(&'static self) -> CriticalStats {
+        self.inner.stats()
+    }
+    /// Initializes the CriticalStatic now if it has not been already, so the first enter is
+    /// not slowed by it. This is idempotent and may be called from any number of threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if initialization fails, as the first enter would.
+    pub fn ensure_init(&'static self) {
+        self.init_once()
+    }
+    /// Returns true if the CriticalStatic has been initialized, without initializing it.
+    pub fn is_initialized(&'static self) -> bool {
+        self.init_state() == InitState::Initialized
+    }
+    /// Returns true if the CriticalStatic failed to initialize and is using the spin lock of
+    /// the spin-fallback feature instead, without initializing it. Always false without
+    /// that feature.
+    pub fn is_fallback(&'static self) -> bool {
+        self.inner.in_fallback()
+    }
+    /// Returns the lazy initialization state of the CriticalStatic, without initializing it.
+    /// Another thread may advance the state at any moment after this returns, but an
+    /// Initialized CriticalStatic stays initialized.
+    pub fn init_state(&'static self) -> InitState {
+        self.load_init_state()
+    }
+    // Not 'static, for Debug.
+    fn load_init_state(&self) -> InitState {
+        match self.init.load(Ordering::Acquire) {
+            UNINITIALIZED => InitState::Uninitialized,
+            INITIALIZING => InitState::Initializing,
+            INITIALIZED => InitState::Initialized,
+            _ => InitState::Poisoned,
+        }
     }
     /// Gets a thin reference to the CriticalStatic, bypassing initialization checks
     /// on future operations. The returned reference is Copy, Send, and Sync.
@@ -157,81 +241,366 @@ impl CriticalStatic {
             Init,
         )
     }
-    // Need to re-think these manual init/delete methods
-    // pub unsafe fn assume_uninit(&'static self) -> CriticalStaticRef<Uninit> {
-    //     CriticalStaticRef(&self.inner, Uninit)
-    // }
-    // pub unsafe fn delete(&'static self) -> CriticalStaticRef<Uninit> {
-    //     delete_cs(self.lpCriticalSection());
-    //     self.assume_uninit()
-    // }
-    // pub unsafe fn init(&'static self) {
-    //     self.inner.clear_poison_unsynced();
-    //     init_cs(self.lpCriticalSection())
-    // }
-    // pub unsafe fn init_with_spin_count(&'static self, spin_count: u32) -> CriticalStaticRef<Init> {
-    //     self.inner.clear_poison_unsynced();
-    //     init_cs_with_spin_count(self.lpCriticalSection(), spin_count);
-    //     self.get_ref()
-    // }
+    /// Initializes the CriticalStatic now with the provided settings, instead of lazily on
+    /// first use with the spin count it was constructed with. This allows settings which are
+    /// only known at runtime.
+    ///
+    /// Fails with ERROR_ALREADY_INITIALIZED if it has already been initialized, or another
+    /// thread is initializing it. If initialization itself fails, the OS error code is
+    /// returned and the CriticalStatic can no longer be used, as if init had panicked. With
+    /// the spin-fallback feature, it falls back to the spin lock instead, as lazy init does.
+    pub fn init_from_config(
+        &'static self,
+        config: CriticalSectionConfig,
+    ) -> Result<CriticalStaticRef<Init>, u32> {
+        if self
+            .init
+            .compare_exchange(
+                UNINITIALIZED,
+                INITIALIZING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return Err(ERROR_ALREADY_INITIALIZED);
+        }
+        // Safety: we won the race above, so nothing else is initializing or using it.
+        let result = unsafe {
+            init_cs_ex(
+                self.lpCriticalSection(),
+                config.spin_count().get(),
+                config.flags(),
+            )
+        };
+        match result {
+            Ok(()) => {
+                self.init.store(INITIALIZED, Ordering::Release);
+                Ok(CriticalStaticRef(&self.inner, Init))
+            }
+            #[cfg(feature = "spin-fallback")]
+            Err(_) => {
+                self.inner.set_fallback(true);
+                self.init.store(INITIALIZED, Ordering::Release);
+                Ok(CriticalStaticRef(&self.inner, Init))
+            }
+            #[cfg(not(feature = "spin-fallback"))]
+            Err(code) => {
+                self.init.store(POISONED, Ordering::Relaxed);
+                Err(code)
+            }
+        }
+    }
+    /// Deletes the underlying CRITICAL_SECTION and returns the CriticalStatic to the
+    /// uninitialized state, so the next use initializes it again, as it did the first time.
+    /// Does nothing if it is not initialized.
+    ///
+    /// The poison flag is kept, and re-initialization on the next use does not clear it
+    /// either, so recovery code which re-creates the section still sees that the data it
+    /// protects may be suspect. To start clean instead, call clear_poison() on the guard
+    /// from the next enter().
+    ///
+    /// # Safety
+    ///
+    /// No thread may be entered, or use the CriticalStatic at all, while this is called.
+    /// Any CriticalStaticRef from get_ref() must not be used afterwards, since it skips the
+    /// initialization check. In debug builds, using one panics.
+    pub unsafe fn delete(&'static self) {
+        if self
+            .init
+            .compare_exchange(
+                INITIALIZED,
+                INITIALIZING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            if self.inner.in_fallback() {
+                // Never initialized, so there is nothing to delete. Try the OS again next time.
+                #[cfg(feature = "spin-fallback")]
+                self.inner.set_fallback(false);
+            } else {
+                delete_cs(self.lpCriticalSection());
+            }
+            // Zeroed, so a stale CriticalStaticRef is caught by its debug check.
+            self.lpCriticalSection().write(CRIT_ZEROED);
+            self.init.store(UNINITIALIZED, Ordering::Release);
+        }
+    }
 }
 
-impl<State> CriticalStaticRef<State> {
-    #[allow(non_snake_case)]
-    fn lpCriticalSection(&self) -> *mut CRITICAL_SECTION {
-        self.0.critical.get()
+impl Default for CriticalStatic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CriticalStatic {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        let state = match self.load_init_state() {
+            InitState::Uninitialized => "uninitialized",
+            InitState::Initializing => "initializing",
+            InitState::Initialized => "initialized",
+            InitState::Poisoned => "poisoned",
+        };
+        write!(out, "CriticalStatic: {:p}", &self.inner)?;
+        self.inner.write_name(out)?;
+        write!(out, " ({}, poisoned: {})", state, self.inner.is_poisoned())
+    }
+}
+
+// Equality and hashing by identity, like CriticalSection.
+impl<State> PartialEq for CriticalStaticRef<State> {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+impl<State> Eq for CriticalStaticRef<State> {}
+impl<State> Hash for CriticalStaticRef<State> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0 as *const PoisonableCriticalSection as usize).hash(state)
     }
 }
 
-// Need to re-think this
-// impl CriticalStaticRef<Uninit> {
-//     pub fn init(self) -> CriticalStaticRef<Init> {
-//         unsafe {
-//             self.0.clear_poison_unsynced();
-//             init_cs(self.lpCriticalSection());
-//         }
-//         CriticalStaticRef(self.0, Init)
-//     }
-//     pub fn init_with_spin_count(self, spin_count: u32) -> CriticalStaticRef<Init> {
-//         unsafe {
-//             self.0.clear_poison_unsynced();
-//             init_cs_with_spin_count(self.lpCriticalSection(), spin_count);
-//         }
-//         CriticalStaticRef(self.0, Init)
-//     }
-// }
+impl<State> AsRef<SectionId> for CriticalStaticRef<State> {
+    fn as_ref(&self) -> &SectionId {
+        SectionId::of(self.0)
+    }
+}
+impl<State> Borrow<SectionId> for CriticalStaticRef<State> {
+    fn borrow(&self) -> &SectionId {
+        SectionId::of(self.0)
+    }
+}
+
+impl<State> fmt::Debug for CriticalStaticRef<State> {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(
+            out,
+            "CriticalStaticRef: {:p} (poisoned: {})",
+            self.0,
+            self.0.is_poisoned()
+        )
+    }
+}
 
 impl CriticalStaticRef<Init> {
+    // Only for storage which is already initialized, and never deleted.
+    #[cfg(feature = "CriticalSection")]
+    pub(crate) fn from_initialized(inner: &'static PoisonableCriticalSection) -> Self {
+        CriticalStaticRef(inner, Init)
+    }
+    // A CriticalStaticRef<Init> can only be made once initialized, so this only checks
+    // in debug builds, in case that guarantee is ever broken.
+    fn debug_check_init(self) {
+        if cfg!(debug_assertions) && !self.0.in_fallback() && self.0.looks_zeroed() {
+            panic!("CriticalStaticRef<Init> used before its critical section was initialized")
+        }
+    }
     pub fn enter(self) -> EnteredCritical<'static> {
-        // Safety: might panic, no return value. Naturally thread-safe.
+        self.debug_check_init();
+        // Safety: no return value. Naturally thread-safe.
         unsafe {
-            enter_cs(self.lpCriticalSection());
+            self.0.enter_raw();
             EnteredCritical::new(self.0)
         }
     }
     pub fn try_enter(self) -> Option<EnteredCritical<'static>> {
-        // Safety: returns non-zero if we are in critical section when call returns.
+        self.debug_check_init();
+        // Safety: returns true if we are in critical section when call returns.
         // Naturally thread-safe.
         unsafe {
-            match try_enter_cs(self.lpCriticalSection()) {
-                0 => None,
-                _ => Some(EnteredCritical::new(self.0)),
+            if self.0.try_enter_raw() {
+                Some(EnteredCritical::new(self.0))
+            } else {
+                self.0.record_try_enter_failure();
+                None
             }
         }
     }
-    pub fn set_spin_count(self, spin_count: u32) -> u32 {
-        // Safety: cannot fail. Returns previous spin_count. Naturally thread-safe.
-        unsafe { set_cs_spin_count(self.lpCriticalSection(), spin_count) }
+    pub fn set_spin_count(self, spin_count: impl Into<SpinCount>) -> u32 {
+        self.0.set_spin_count(spin_count.into())
+    }
+    pub fn spin_count(self) -> u32 {
+        self.0.spin_count()
+    }
+    /// Returns true if any thread currently holds this critical section, without
+    /// attempting to enter it. This is a best-effort snapshot which may be stale as soon
+    /// as it is returned, and is intended for monitoring rather than synchronization.
+    pub fn is_locked(self) -> bool {
+        self.0.is_locked()
+    }
+    /// Returns true if a thread panicked while inside this critical section. The result may
+    /// be stale as soon as it is returned.
+    pub fn is_poisoned(self) -> bool {
+        self.0.is_poisoned()
+    }
+    /// Returns the id of the thread which currently owns this critical section, or None if
+    /// it is not held. This is only a snapshot intended for debugging.
+    pub fn owning_thread_id(self) -> Option<u32> {
+        self.0.owning_thread_id()
+    }
+    #[cfg(feature = "metrics")]
+    pub fn stats(self) -> CriticalStats {
+        self.0.stats()
+    }
+}
+
+impl Lockable for CriticalStaticRef<Init> {
+    type Guard<'a> = EnteredCritical<'static>;
+    fn enter(&self) -> EnteredCritical<'static> {
+        CriticalStaticRef::enter(*self)
+    }
+    fn try_enter(&self) -> Option<EnteredCritical<'static>> {
+        CriticalStaticRef::try_enter(*self)
+    }
+}
+
+/// CriticalStaticMutex protects a value of type `T` with a CriticalStatic. Like CriticalStatic,
+/// it can be constructed statically and never allocates, which makes it suitable for protecting
+/// allocator state.
+///
+/// Unlike CriticalStatic, a CriticalStaticMutex may not be re-entered by the thread which
+/// currently holds it, since that would hand out two mutable references to the same data.
+/// Attempting to do so panics rather than deadlocking.
+pub struct CriticalStaticMutex<T> {
+    critical: CriticalStatic,
+    data: UnsafeCell<T>,
+}
+
+/// Grants access to the data protected by a CriticalStaticMutex. The mutex is unlocked when
+/// this is dropped.
+pub struct CriticalStaticMutexGuard<T: 'static> {
+    entered: EnteredCritical<'static>,
+    data: &'static UnsafeCell<T>,
+}
+
+/// A thin reference to an initialized CriticalStaticMutex, bypassing initialization checks
+/// on future operations. Obtained from CriticalStaticMutex::get_ref().
+pub struct CriticalStaticMutexRef<T: 'static> {
+    critical: CriticalStaticRef<Init>,
+    data: &'static UnsafeCell<T>,
+}
+
+// Safety: access to data is only ever granted while the critical section is entered.
+unsafe impl<T: Send> Sync for CriticalStaticMutex<T> {}
+unsafe impl<T: Send> Send for CriticalStaticMutexRef<T> {}
+unsafe impl<T: Send> Sync for CriticalStaticMutexRef<T> {}
+
+impl<T> Copy for CriticalStaticMutexRef<T> {}
+impl<T> Clone for CriticalStaticMutexRef<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> CriticalStaticMutex<T> {
+    /// Creates a new CriticalStaticMutex protecting the provided value.
+    pub const fn new(value: T) -> Self {
+        Self {
+            critical: CriticalStatic::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+    /// Creates a new CriticalStaticMutex protecting the provided value, which will be
+    /// initialized with the provided spin_count.
+    pub const fn with_spin_count(value: T, spin_count: u32) -> Self {
+        Self {
+            critical: CriticalStatic::with_spin_count(spin_count),
+            data: UnsafeCell::new(value),
+        }
+    }
+    /// Enters the underlying Critical Section and returns a guard granting access to
+    /// the protected data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the calling thread already holds this CriticalStaticMutex.
+    pub fn lock(&'static self) -> CriticalStaticMutexGuard<T> {
+        CriticalStaticMutexGuard::new(self.critical.enter(), &self.data)
+    }
+    /// Gets a thin reference to the CriticalStaticMutex, bypassing initialization checks
+    /// on future operations. The returned reference is Copy, and is Send and Sync if T is Send.
+    pub fn get_ref(&'static self) -> CriticalStaticMutexRef<T> {
+        CriticalStaticMutexRef {
+            critical: self.critical.get_ref(),
+            data: &self.data,
+        }
+    }
+}
+
+impl<T> CriticalStaticMutexRef<T> {
+    /// Enters the underlying Critical Section and returns a guard granting access to
+    /// the protected data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the calling thread already holds this CriticalStaticMutex.
+    pub fn lock(self) -> CriticalStaticMutexGuard<T> {
+        CriticalStaticMutexGuard::new(self.critical.enter(), self.data)
+    }
+}
+
+impl<T> CriticalStaticMutexGuard<T> {
+    fn new(entered: EnteredCritical<'static>, data: &'static UnsafeCell<T>) -> Self {
+        if entered.recursion_count() > 1 {
+            drop(entered);
+            panic!("CriticalStaticMutex is already locked by the current thread")
+        }
+        Self { entered, data }
+    }
+    /// Returns true if a thread panicked while holding this mutex. This is an associated
+    /// function so as not to shadow methods on `T`.
+    pub fn is_poisoned(guard: &Self) -> bool {
+        guard.entered.is_poisoned()
+    }
+    /// Clears the poison flag. This is an associated function so as not to shadow
+    /// methods on `T`.
+    pub fn clear_poison(guard: &Self) {
+        guard.entered.clear_poison()
+    }
+}
+
+impl<T> Deref for CriticalStaticMutexGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: we are entered, and re-entry is rejected in new()
+        unsafe { &*self.data.get() }
+    }
+}
+
+impl<T> DerefMut for CriticalStaticMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: we are entered, and re-entry is rejected in new()
+        unsafe { &mut *self.data.get() }
     }
-    // pub unsafe fn delete(self) -> CriticalStaticRef<Uninit> {
-    //     delete_cs(self.lpCriticalSection());
-    //     CriticalStaticRef(self.0, Uninit)
-    // }
+}
+
+/// Declares a static CriticalStaticMutex, expanding to its const constructor. A spin count
+/// may follow the initial value, and attributes and visibility are passed through.
+///
+/// `critical_static!(static FOO: u32 = 0);` declares `static FOO: CriticalStaticMutex<u32>`,
+/// and `critical_static!(pub static BAR: u32 = 0; spin = 4000);` also sets a spin count.
+#[macro_export]
+macro_rules! critical_static {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr; spin = $spin:expr $(;)?) => {
+        $(#[$attr])*
+        $vis static $name: $crate::CriticalStaticMutex<$ty> =
+            $crate::CriticalStaticMutex::with_spin_count($init, $spin);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr $(;)?) => {
+        $(#[$attr])*
+        $vis static $name: $crate::CriticalStaticMutex<$ty> =
+            $crate::CriticalStaticMutex::new($init);
+    };
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::CriticalStatic;
+    #[cfg(all(feature = "std", not(feature = "no-poison")))]
+    use crate::CriticalStaticMutexGuard;
+    use crate::{CriticalStatic, CriticalStaticMutex};
     use std::thread;
 
     #[test]
@@ -261,9 +630,56 @@ mod tests {
             }
         }
         assert_eq!(98, unsafe { X });
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
         assert!(CRITICAL.enter().is_poisoned());
     }
 
+    #[test]
+    #[cfg(all(feature = "std", not(feature = "no-poison")))]
+    fn poison_hook() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static CRITICAL: CriticalStatic = CriticalStatic::new().with_name("poison_hook");
+        static POISONINGS: AtomicUsize = AtomicUsize::new(0);
+        // The hook is global, so ignore sections other tests poison.
+        crate::set_poison_hook(Some(|entered| {
+            if entered.name() == Some("poison_hook") {
+                POISONINGS.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+        let poison = || {
+            thread::spawn(|| {
+                let _entered = CRITICAL.enter();
+                panic!("poison it")
+            })
+            .join()
+            .unwrap_err()
+        };
+        poison();
+        poison();
+        assert_eq!(1, POISONINGS.load(Ordering::Relaxed));
+        CRITICAL.enter().clear_poison();
+        poison();
+        assert_eq!(2, POISONINGS.load(Ordering::Relaxed));
+        crate::set_poison_hook(None);
+    }
+
+    #[test]
+    fn ref_crosses_threads() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        let (ref_tx, ref_rx) = std::sync::mpsc::channel();
+        let (held_tx, held_rx) = std::sync::mpsc::channel();
+        let entered = CRITICAL.enter();
+        let handle = thread::spawn(move || {
+            let crit_ref: crate::CriticalStaticRef<super::Init> = ref_rx.recv().unwrap();
+            held_tx.send(crit_ref.try_enter().is_none()).unwrap();
+            crit_ref.enter().leave();
+        });
+        ref_tx.send(CRITICAL.get_ref()).unwrap();
+        assert!(held_rx.recv().unwrap());
+        drop(entered);
+        handle.join().unwrap();
+    }
+
     #[test]
     fn threads_on_the_wall_ref() {
         static mut X: usize = 0;
@@ -292,6 +708,321 @@ mod tests {
             }
         }
         assert_eq!(98, unsafe { X });
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
         assert!(crit_ref.enter().is_poisoned());
     }
+
+    #[test]
+    fn mutex_threads_on_the_wall() {
+        static MUTEX: CriticalStaticMutex<usize> = CriticalStaticMutex::new(0);
+        let mut handles = Vec::with_capacity(99);
+        for i in 0..99 {
+            handles.push(thread::spawn(move || {
+                let mut x = MUTEX.lock();
+                if i == 0 {
+                    panic!("Take one down")
+                }
+                let next = 1 + *x;
+                thread::yield_now();
+                *x = next;
+            }));
+        }
+        for (i, handle) in handles.into_iter().enumerate() {
+            if i == 0 {
+                handle.join().unwrap_err();
+            } else {
+                handle.join().unwrap();
+            }
+        }
+        let x = MUTEX.lock();
+        assert_eq!(98, *x);
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
+        assert!(CriticalStaticMutexGuard::is_poisoned(&x));
+    }
+
+    #[test]
+    fn mutex_threads_on_the_wall_ref() {
+        static MUTEX: CriticalStaticMutex<usize> = CriticalStaticMutex::with_spin_count(0, 4000);
+        let mutex_ref = MUTEX.get_ref();
+        let mut handles = Vec::with_capacity(99);
+        for _ in 0..99 {
+            handles.push(thread::spawn(move || {
+                let mut x = mutex_ref.lock();
+                let next = 1 + *x;
+                thread::yield_now();
+                *x = next;
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(99, *mutex_ref.lock());
+    }
+
+    #[test]
+    fn is_locked_ref() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        let crit_ref = CRITICAL.get_ref();
+        assert!(!crit_ref.is_locked());
+        let entered = crit_ref.enter();
+        assert!(crit_ref.is_locked());
+        entered.leave();
+        assert!(!crit_ref.is_locked());
+    }
+
+    #[test]
+    fn spin_count() {
+        static CRITICAL: CriticalStatic = CriticalStatic::with_spin_count(4000);
+        assert_eq!(4000, CRITICAL.spin_count());
+        let crit_ref = CRITICAL.get_ref();
+        assert_eq!(4000, crit_ref.set_spin_count(100));
+        assert_eq!(100, crit_ref.spin_count());
+    }
+
+    #[test]
+    fn debug() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        assert!(format!("{:?}", CRITICAL).contains("(uninitialized, poisoned: false)"));
+        let entered = CRITICAL.enter();
+        assert!(format!("{:?}", CRITICAL).contains("(initialized, poisoned: false)"));
+        assert!(format!("{:?}", entered).contains("(recursion count: 1, poisoned: false)"));
+        #[cfg(feature = "CriticalSection")]
+        assert!(entered.section().is_none());
+        entered.leave();
+        assert!(format!("{:?}", CRITICAL.get_ref()).contains("(poisoned: false)"));
+    }
+
+    #[test]
+    fn ref_inspectors() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        assert!(!CRITICAL.is_locked());
+        let crit_ref = CRITICAL.get_ref();
+        assert_eq!(None, crit_ref.owning_thread_id());
+        let entered = crit_ref.enter();
+        assert!(CRITICAL.is_locked());
+        assert_eq!(entered.owning_thread_id(), crit_ref.owning_thread_id());
+        assert_eq!(entered.owning_thread_id(), CRITICAL.owning_thread_id());
+        entered.leave();
+        #[cfg(all(feature = "std", not(feature = "no-poison")))]
+        thread::spawn(move || {
+            let _entered = crit_ref.enter();
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        assert_eq!(
+            cfg!(all(feature = "std", not(feature = "no-poison"))),
+            crit_ref.is_poisoned()
+        );
+        assert_eq!(
+            cfg!(all(feature = "std", not(feature = "no-poison"))),
+            CRITICAL.is_poisoned()
+        );
+    }
+
+    #[test]
+    // Hash and Eq only look at the address, never the interior-mutable contents.
+    #[allow(clippy::mutable_key_type)]
+    fn ref_eq() {
+        static A: CriticalStatic = CriticalStatic::new();
+        static B: CriticalStatic = CriticalStatic::new();
+        assert_eq!(A.get_ref(), A.get_ref());
+        assert_ne!(A.get_ref(), B.get_ref());
+        let set: std::collections::HashSet<_> = vec![A.get_ref(), B.get_ref(), A.get_ref()]
+            .into_iter()
+            .collect();
+        assert_eq!(2, set.len());
+    }
+
+    #[test]
+    fn init_from_config() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        let config = crate::CriticalSectionConfig {
+            spin_count: Some(1234),
+            no_debug_info: true,
+            ..Default::default()
+        };
+        let critical = CRITICAL.init_from_config(config).unwrap();
+        assert_eq!(1234, critical.spin_count());
+        assert!(CRITICAL.init_from_config(config).is_err());
+        assert_eq!(1234, CRITICAL.spin_count());
+    }
+
+    #[test]
+    fn init_state() {
+        use crate::InitState;
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        assert!(!CRITICAL.is_initialized());
+        assert_eq!(InitState::Uninitialized, CRITICAL.init_state());
+        CRITICAL.enter().leave();
+        assert!(CRITICAL.is_initialized());
+        assert_eq!(InitState::Initialized, CRITICAL.init_state());
+    }
+
+    #[test]
+    fn ensure_init() {
+        use crate::InitState;
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        let handles: Vec<_> = (0..4)
+            .map(|_| thread::spawn(|| CRITICAL.ensure_init()))
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(CRITICAL.is_initialized());
+        CRITICAL.ensure_init();
+        assert_eq!(InitState::Initialized, CRITICAL.init_state());
+    }
+
+    #[test]
+    fn with_name() {
+        static CRITICAL: CriticalStatic = CriticalStatic::with_spin_count(100).with_name("alloc");
+        assert_eq!(Some("alloc"), CRITICAL.name());
+        assert!(format!("{:?}", CRITICAL).contains(" \"alloc\" ("));
+        assert_eq!(Some("alloc"), CRITICAL.enter().name());
+        assert_eq!(100, CRITICAL.spin_count());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "before its critical section was initialized")]
+    fn enter_zeroed_ref() {
+        use super::{CriticalStaticRef, Init};
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        CriticalStaticRef(&CRITICAL.inner, Init).enter();
+    }
+
+    #[test]
+    fn delete_then_enter() {
+        static CRITICAL: CriticalStatic = CriticalStatic::with_spin_count(100);
+        CRITICAL.enter().leave();
+        unsafe { CRITICAL.delete() };
+        assert!(!CRITICAL.is_initialized());
+        assert!(!CRITICAL.is_locked());
+        let entered = CRITICAL.enter();
+        assert!(CRITICAL.is_initialized());
+        assert_eq!(1, entered.recursion_count());
+        assert_eq!(100, entered.spin_count());
+        drop(entered);
+        unsafe { CRITICAL.delete() };
+        // Deleting again while uninitialized does nothing.
+        unsafe { CRITICAL.delete() };
+        CRITICAL.try_enter().unwrap().leave();
+    }
+
+    #[test]
+    #[cfg(all(feature = "std", not(feature = "no-poison")))]
+    fn delete_keeps_poison() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        thread::spawn(|| {
+            let _entered = CRITICAL.enter();
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        unsafe { CRITICAL.delete() };
+        assert!(CRITICAL.is_poisoned());
+        let entered = CRITICAL.enter();
+        assert!(entered.is_poisoned());
+        entered.clear_poison();
+        drop(entered);
+        assert!(!CRITICAL.is_poisoned());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "before its critical section was initialized")]
+    fn stale_ref_after_delete() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        let crit_ref = CRITICAL.get_ref();
+        unsafe { CRITICAL.delete() };
+        crit_ref.enter();
+    }
+
+    #[test]
+    fn initialized_ref_is_not_zeroed() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        let critical = CRITICAL.get_ref();
+        assert!(!critical.0.looks_zeroed());
+        let entered = critical.enter();
+        assert!(!critical.0.looks_zeroed());
+        drop(entered);
+    }
+
+    #[test]
+    #[cfg(feature = "spin-fallback")]
+    fn spin_fallback() {
+        use super::INITIALIZED;
+        use std::sync::atomic::Ordering;
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        // Init cannot be made to fail, so switch to the fallback as a failed init would.
+        CRITICAL.inner.set_fallback(true);
+        CRITICAL.init.store(INITIALIZED, Ordering::Release);
+        assert!(CRITICAL.is_fallback());
+        assert_eq!(0, CRITICAL.set_spin_count(100));
+        let entered = CRITICAL.enter();
+        assert!(CRITICAL.is_locked());
+        assert_eq!(
+            Some(crate::wrapper::current_thread_id()),
+            entered.owning_thread_id()
+        );
+        assert_eq!(1, entered.recursion_count());
+        entered.assert_held();
+        thread::spawn(|| assert!(CRITICAL.get_ref().try_enter().is_none()))
+            .join()
+            .unwrap();
+        drop(entered);
+        assert!(!CRITICAL.is_locked());
+        let _ = thread::spawn(|| {
+            let _entered = CRITICAL.enter();
+            panic!("poison the fallback")
+        })
+        .join();
+        assert!(CRITICAL.is_poisoned());
+        assert!(CRITICAL.try_enter().is_some());
+        unsafe { CRITICAL.delete() };
+        assert!(!CRITICAL.is_fallback());
+        assert!(!CRITICAL.is_initialized());
+    }
+
+    #[test]
+    // Keys hash by address, like the SectionId they are looked up with.
+    #[allow(clippy::mutable_key_type)]
+    fn section_id() {
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        let mut set = std::collections::HashSet::new();
+        set.insert(CRITICAL.get_ref());
+        assert!(set.contains(CRITICAL.enter().id()));
+        assert_eq!(CRITICAL.enter().id(), CRITICAL.get_ref().as_ref());
+    }
+
+    #[test]
+    fn lockable() {
+        fn work<L: crate::Lockable>(lock: &L) -> bool {
+            let _entered = lock.enter();
+            lock.try_enter().is_some()
+        }
+        static CRITICAL: CriticalStatic = CriticalStatic::new();
+        assert!(work(&CRITICAL.get_ref()));
+        assert!(!CRITICAL.is_locked());
+    }
+
+    #[test]
+    fn critical_static_macro() {
+        struct Counter(u32);
+        impl Counter {
+            const fn new() -> Self {
+                Self(0)
+            }
+        }
+        crate::critical_static!(static FOO: u32 = 1);
+        crate::critical_static!(
+            /// With a spin count
+            static BAR: Counter = Counter::new(); spin = 4000;
+        );
+        *FOO.lock() += 1;
+        BAR.lock().0 += 1;
+        assert_eq!(2, *FOO.lock());
+        assert_eq!(1, BAR.lock().0);
+    }
 }
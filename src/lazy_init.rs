@@ -0,0 +1,147 @@
+// This module should not use std.
+
+// The lazy initialization shared by CriticalStatic and PinnedCriticalSection, which both
+// initialize their CRITICAL_SECTION on first use, from whichever thread gets there first.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub(crate) const UNINITIALIZED: usize = 0;
+pub(crate) const INITIALIZING: usize = 1;
+pub(crate) const INITIALIZED: usize = 2;
+pub(crate) const POISONED: usize = 3;
+
+// The init state of a lazily initialized critical section. This is a trait so that
+// run_init_once() can also be model checked with loom's atomics, while the sections keep
+// core's for their const fn new().
+pub(crate) trait InitCell {
+    fn load(&self, order: Ordering) -> usize;
+    fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<usize, usize>;
+    fn store(&self, val: usize, order: Ordering);
+    // Called while waiting for another thread to finish initializing.
+    fn wait(&self);
+}
+
+impl InitCell for AtomicUsize {
+    fn load(&self, order: Ordering) -> usize {
+        self.load(order)
+    }
+    fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<usize, usize> {
+        self.compare_exchange(current, new, success, failure)
+    }
+    fn store(&self, val: usize, order: Ordering) {
+        self.store(val, order)
+    }
+    fn wait(&self) {
+        core::hint::spin_loop()
+    }
+}
+
+// Runs init exactly once among all threads racing on state, and returns once it has
+// completed. Panics in every thread if it fails or panics.
+pub(crate) fn run_init_once(state: &impl InitCell, init: impl FnOnce() -> Result<(), u32>) {
+    struct PoisonCatcher<'a, S: InitCell>(&'a S);
+    impl<S: InitCell> Drop for PoisonCatcher<'_, S> {
+        fn drop(&mut self) {
+            self.0.store(POISONED, Ordering::Relaxed)
+        }
+    }
+    if INITIALIZED == state.load(Ordering::Acquire) {
+        return;
+    }
+    if state
+        .compare_exchange(
+            UNINITIALIZED,
+            INITIALIZING,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        )
+        .is_ok()
+    {
+        let catcher = PoisonCatcher(state);
+        init().expect("Failed to initialize critical section");
+        core::mem::forget(catcher);
+        state.store(INITIALIZED, Ordering::Release);
+    } else {
+        // It won't take long, just spin
+        loop {
+            let status = state.load(Ordering::Acquire);
+            if INITIALIZED == status {
+                return;
+            } else if POISONED == status {
+                panic!("Critical Section init failed")
+            }
+            state.wait();
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::{run_init_once, InitCell, INITIALIZED};
+    use loom::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    impl InitCell for AtomicUsize {
+        fn load(&self, order: Ordering) -> usize {
+            self.load(order)
+        }
+        fn compare_exchange(
+            &self,
+            current: usize,
+            new: usize,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<usize, usize> {
+            self.compare_exchange(current, new, success, failure)
+        }
+        fn store(&self, val: usize, order: Ordering) {
+            self.store(val, order)
+        }
+        fn wait(&self) {
+            thread::yield_now()
+        }
+    }
+
+    #[test]
+    fn racing_init() {
+        loom::model(|| {
+            let state = Arc::new(AtomicUsize::new(0));
+            // Stands in for InitializeCriticalSection, counting how often it runs
+            let inits = Arc::new(AtomicUsize::new(0));
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let (state, inits) = (state.clone(), inits.clone());
+                    thread::spawn(move || {
+                        run_init_once(&*state, || {
+                            inits.fetch_add(1, Ordering::Relaxed);
+                            Ok(())
+                        });
+                        // Initialization must be visible once run_init_once() returns
+                        assert_eq!(1, inits.load(Ordering::Relaxed));
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert_eq!(INITIALIZED, state.load(Ordering::Relaxed));
+        });
+    }
+}
@@ -1,12 +1,83 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(not(any(feature = "winapi", feature = "windows-sys")))]
+compile_error!("Either the winapi or windows-sys feature must be enabled");
+
+#[cfg(any(feature = "CriticalSection", feature = "lock-api"))]
+extern crate alloc;
+
 pub(crate) mod common;
-pub use common::EnteredCritical;
+#[cfg(feature = "metrics")]
+pub use common::CriticalStats;
+#[cfg(all(feature = "CriticalSection", feature = "CriticalStatic"))]
+pub use common::CriticalStorage;
+#[cfg(not(feature = "no-poison"))]
+pub use common::{set_poison_hook, PoisonHook};
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+pub use common::{CriticalSectionConfig, Lockable, RawHeldToken};
+pub use common::{EnteredCritical, LeaveOutcome, SectionId, SendEnteredCritical, SpinCount};
+#[cfg(feature = "CriticalSection")]
+pub use common::{NestedGuards, PoisonPolicy};
 pub(crate) mod wrapper;
 
+pub mod raw;
+
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+#[cfg(feature = "CriticalSection")]
+mod backoff;
+#[cfg(feature = "CriticalSection")]
+pub use backoff::{Backoff, ExponentialBackoff};
 #[cfg(feature = "CriticalSection")]
 mod crit;
+#[cfg(feature = "deadlock-detection")]
+mod lock_order;
+#[cfg(all(feature = "CriticalSection", feature = "std"))]
+pub use crit::TimedEnteredCritical;
 #[cfg(feature = "CriticalSection")]
-pub use crit::CriticalSection;
+pub use crit::{
+    CriticalSection, CriticalSectionBuilder, LazyCriticalSection, OwnedEnteredCritical,
+    TryEnterResult, TryEnterStatus,
+};
 #[cfg(feature = "CriticalStatic")]
 mod crit_static;
 #[cfg(feature = "CriticalStatic")]
-pub use crit_static::{CriticalStatic, CriticalStaticRef};
+pub use crit_static::{
+    CriticalStatic, CriticalStaticMutex, CriticalStaticMutexGuard, CriticalStaticMutexRef,
+    CriticalStaticRef, InitState,
+};
+#[cfg(feature = "CriticalStatic")]
+mod striped;
+#[cfg(feature = "CriticalStatic")]
+pub use striped::StripedCritical;
+#[cfg(feature = "CriticalMutex")]
+mod mutex;
+#[cfg(feature = "CriticalMutex")]
+pub use mutex::{
+    CriticalMutex, CriticalMutexGuard, MappedCriticalMutexGuard, TryLockError, TryLockResult,
+};
+#[cfg(feature = "CriticalCondvar")]
+mod condvar;
+#[cfg(feature = "CriticalCondvar")]
+pub use condvar::CriticalCondvar;
+#[cfg(any(feature = "CriticalStatic", feature = "PinnedCriticalSection"))]
+mod lazy_init;
+#[cfg(feature = "PinnedCriticalSection")]
+mod pinned;
+#[cfg(feature = "PinnedCriticalSection")]
+pub use pinned::PinnedCriticalSection;
+#[cfg(feature = "SlimRwLock")]
+mod srwlock;
+#[cfg(feature = "SlimRwLock")]
+pub use srwlock::{SlimReadGuard, SlimRwLock, SlimWriteGuard};
+#[cfg(feature = "lock-api")]
+mod crit_raw;
+#[cfg(feature = "lock-api")]
+pub use crit_raw::CritRaw;
+/// Ready-made lock_api types backed by CritRaw. These are separate from the crate root's
+/// CriticalMutex, which is not built on lock_api.
+#[cfg(feature = "lock-api")]
+pub mod lock_api {
+    pub use crate::crit_raw::{CritRaw, CriticalMutex, CriticalMutexGuard};
+}
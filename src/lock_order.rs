@@ -0,0 +1,86 @@
+// Lock order tracking for the deadlock-detection feature.
+//
+// Every tracked CriticalSection gets an id from a global counter when it is created, so ids
+// follow creation order. Each thread keeps a stack of the ids it holds. Entering a section
+// while holding one with a higher id is a lock order violation, since another thread taking
+// the same two sections in creation order could deadlock against it. Id 0 is never tracked.
+
+use std::{
+    backtrace::Backtrace,
+    cell::RefCell,
+    fmt::{self, Display, Formatter},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static HELD: RefCell<Vec<(Label, Backtrace)>> = const { RefCell::new(Vec::new()) };
+}
+
+// A section's id, and name if it has one, for messages.
+#[derive(Clone, Copy)]
+struct Label(u64, Option<&'static str>);
+
+impl Display for Label {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        match self.1 {
+            Some(name) => write!(out, "{} ({:?})", self.0, name),
+            None => write!(out, "{}", self.0),
+        }
+    }
+}
+
+pub(crate) fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// Called before blocking to enter, so a violation panics rather than deadlocking.
+pub(crate) fn check(id: u64, name: Option<&'static str>) {
+    if id == 0 || !cfg!(debug_assertions) {
+        return;
+    }
+    HELD.with(|held| {
+        let held = held.borrow();
+        if held.iter().any(|(held, _)| held.0 == id) {
+            // Recursive entry cannot deadlock
+            return;
+        }
+        if let Some((held, held_at)) = held.iter().rev().find(|(held, _)| held.0 > id) {
+            let entering = Label(id, name);
+            panic!(
+                "Lock order violation: entering critical section {} while holding {}\n\
+                 {} was entered at:\n{}\n{} is being entered at:\n{}",
+                entering,
+                held,
+                held,
+                held_at,
+                entering,
+                Backtrace::capture()
+            )
+        }
+    })
+}
+
+pub(crate) fn entered(id: u64, name: Option<&'static str>) {
+    if id == 0 || !cfg!(debug_assertions) {
+        return;
+    }
+    HELD.with(|held| {
+        held.borrow_mut()
+            .push((Label(id, name), Backtrace::capture()))
+    });
+}
+
+pub(crate) fn left(id: u64) {
+    if id == 0 || !cfg!(debug_assertions) {
+        return;
+    }
+    // Sections may be left in any order. Ignore a destroyed thread local during thread exit.
+    let _ = HELD.try_with(|held| {
+        let mut held = held.borrow_mut();
+        if let Some(index) = held.iter().rposition(|(held, _)| held.0 == id) {
+            drop(held.remove(index));
+        }
+    });
+}
@@ -0,0 +1,634 @@
+#[cfg(feature = "CriticalCondvar")]
+use crate::CriticalCondvar;
+use crate::{CriticalSection, EnteredCritical};
+
+use std::{
+    cell::UnsafeCell,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+    sync::{LockResult, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// A mutual exclusion primitive protecting a value of type `T`, backed by a CriticalSection.
+///
+/// Unlike a bare CriticalSection, a CriticalMutex may not be re-entered by the thread
+/// which currently holds it, since that would hand out two mutable references to the
+/// same data. Attempting to do so panics rather than deadlocking.
+///
+/// lock(), try_lock(), is_poisoned() and clear_poison() have the same signatures as on
+/// `std::sync::Mutex`, with LockResult and PoisonError from std and a TryLockError of this
+/// crate's own, so code can usually switch over by changing a type alias. The differences:
+///
+/// * It only works on Windows.
+/// * lock() panics if the calling thread already holds the mutex, and try_lock() returns
+///   WouldBlock, since the underlying critical section is recursive.
+/// * into_inner() and get_mut() return the data directly rather than a LockResult.
+pub struct CriticalMutex<T: ?Sized> {
+    critical: CriticalSection,
+    data: UnsafeCell<T>,
+}
+
+// Safety: access to data is only ever granted while the critical section is entered.
+unsafe impl<T: ?Sized + Send> Send for CriticalMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for CriticalMutex<T> {}
+
+/// Grants access to the data protected by a CriticalMutex. The mutex is unlocked when
+/// this is dropped.
+pub struct CriticalMutexGuard<'m, T: ?Sized> {
+    entered: EnteredCritical<'m>,
+    data: &'m UnsafeCell<T>,
+}
+
+/// The error from CriticalMutex::try_lock(), shaped like `std::sync::TryLockError`.
+pub enum TryLockError<T> {
+    /// The mutex was locked, but a thread panicked while holding it. The guard can still
+    /// be recovered with `PoisonError::into_inner`.
+    Poisoned(PoisonError<T>),
+    /// The mutex is held by another thread, or by the calling thread.
+    WouldBlock,
+}
+
+/// The result of CriticalMutex::try_lock(), like `std::sync::TryLockResult`.
+pub type TryLockResult<Guard> = Result<Guard, TryLockError<Guard>>;
+
+/// Grants access to part of the data protected by a CriticalMutex, obtained from
+/// CriticalMutexGuard::map(). The mutex is unlocked when this is dropped.
+pub struct MappedCriticalMutexGuard<'m, U: ?Sized> {
+    entered: EnteredCritical<'m>,
+    data: NonNull<U>,
+    _marker: PhantomData<&'m mut U>,
+}
+
+impl<T> CriticalMutex<T> {
+    /// Creates a new CriticalMutex protecting the provided value.
+    pub fn new(value: T) -> Self {
+        Self {
+            critical: CriticalSection::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+    /// Consumes the CriticalMutex, returning the protected value.
+    pub fn into_inner(self) -> T {
+        let Self { critical, data } = self;
+        drop(critical);
+        data.into_inner()
+    }
+}
+
+impl<T: ?Sized> CriticalMutex<T> {
+    /// Enters the underlying Critical Section and returns a guard granting access to
+    /// the protected data.
+    ///
+    /// If another thread panicked while holding this mutex, an error is returned. The
+    /// guard can still be recovered with `PoisonError::into_inner`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the calling thread already holds this CriticalMutex.
+    pub fn lock(&self) -> LockResult<CriticalMutexGuard<'_, T>> {
+        self.guard(self.critical.enter_unchecked())
+    }
+    /// Tries to lock the mutex without blocking. Fails with WouldBlock if any thread holds
+    /// it, including the calling thread, and with Poisoned if another thread panicked while
+    /// holding it.
+    pub fn try_lock(&self) -> TryLockResult<CriticalMutexGuard<'_, T>> {
+        let entered = self.critical.try_enter().ok_or(TryLockError::WouldBlock)?;
+        if entered.recursion_count() > 1 {
+            return Err(TryLockError::WouldBlock);
+        }
+        Ok(self.guard(entered)?)
+    }
+    /// Returns true if a thread panicked while holding this mutex, without locking it.
+    pub fn is_poisoned(&self) -> bool {
+        self.critical.is_poisoned()
+    }
+    /// Clears the poison flag, so later calls to lock() succeed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the calling thread already holds this CriticalMutex, as lock() does.
+    pub fn clear_poison(&self) {
+        CriticalMutexGuard::clear_poison(&self.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+    /// Like lock(), but gives up and returns None once the provided duration has passed,
+    /// using CriticalSection::try_enter_for(). The poison check still applies once locked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the calling thread already holds this CriticalMutex.
+    pub fn try_lock_for(&self, dur: Duration) -> Option<LockResult<CriticalMutexGuard<'_, T>>> {
+        let entered = self.critical.try_enter_for(dur)?;
+        Some(self.guard(entered))
+    }
+    /// Like try_lock_for(), but gives up once the provided deadline has passed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the calling thread already holds this CriticalMutex.
+    pub fn try_lock_until(
+        &self,
+        deadline: Instant,
+    ) -> Option<LockResult<CriticalMutexGuard<'_, T>>> {
+        let entered = self.critical.try_enter_until(deadline)?;
+        Some(self.guard(entered))
+    }
+    /// Locks the mutex, and if it was poisoned, runs the provided closure to repair the data
+    /// before clearing the poison. Returns a normal guard either way. If the closure panics,
+    /// the mutex stays poisoned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the calling thread already holds this CriticalMutex.
+    pub fn recover_with(&self, f: impl FnOnce(&mut T)) -> CriticalMutexGuard<'_, T> {
+        match self.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => {
+                let mut guard = poisoned.into_inner();
+                f(&mut guard);
+                CriticalMutexGuard::clear_poison(&guard);
+                guard
+            }
+        }
+    }
+    // Rejects re-entry and checks for poison.
+    fn guard<'m>(&'m self, entered: EnteredCritical<'m>) -> LockResult<CriticalMutexGuard<'m, T>> {
+        if entered.recursion_count() > 1 {
+            drop(entered);
+            panic!("CriticalMutex is already locked by the current thread")
+        }
+        CriticalMutexGuard {
+            entered,
+            data: &self.data,
+        }
+        .into_result()
+    }
+    /// Returns a mutable reference to the protected data. No locking is needed since
+    /// the borrow checker guarantees exclusive access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+// Waiting on a CriticalMutexGuard needs std, so it lives here rather than in condvar.rs.
+#[cfg(feature = "CriticalCondvar")]
+impl CriticalCondvar {
+    /// Waits on this condition variable for as long as the condition returns true, so that
+    /// spurious wakeups are never seen by the caller. The condition is checked before the
+    /// first wait, while the mutex is held.
+    ///
+    /// As with CriticalMutex::lock(), an error is returned if the mutex is poisoned once the
+    /// condition returns false.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the underlying critical section has been entered more than once by the
+    /// calling thread.
+    pub fn wait_while<'m, T: ?Sized>(
+        &self,
+        mut guard: CriticalMutexGuard<'m, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> LockResult<CriticalMutexGuard<'m, T>> {
+        while condition(&mut *guard) {
+            guard = guard.wait_on(|entered| (self.wait(entered), false)).0;
+        }
+        guard.into_result()
+    }
+    /// Like wait_while(), but gives up once roughly the provided duration has passed in
+    /// total. Returns the guard, and true if the condition still held when it timed out.
+    /// A duration too large to add to the current time waits without a deadline.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the underlying critical section has been entered more than once by the
+    /// calling thread.
+    pub fn wait_timeout_while<'m, T: ?Sized>(
+        &self,
+        mut guard: CriticalMutexGuard<'m, T>,
+        dur: Duration,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> LockResult<(CriticalMutexGuard<'m, T>, bool)> {
+        let deadline = match Instant::now().checked_add(dur) {
+            Some(deadline) => deadline,
+            None => {
+                return match self.wait_while(guard, condition) {
+                    Ok(guard) => Ok((guard, false)),
+                    Err(poisoned) => Err(PoisonError::new((poisoned.into_inner(), false))),
+                }
+            }
+        };
+        let mut timed_out = false;
+        while condition(&mut *guard) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if timed_out || remaining == Duration::ZERO {
+                return match guard.into_result() {
+                    Ok(guard) => Ok((guard, true)),
+                    Err(poisoned) => Err(PoisonError::new((poisoned.into_inner(), true))),
+                };
+            }
+            let (next, result) = guard.wait_on(|entered| self.wait_timeout(entered, remaining));
+            guard = next;
+            timed_out = result;
+        }
+        match guard.into_result() {
+            Ok(guard) => Ok((guard, false)),
+            Err(poisoned) => Err(PoisonError::new((poisoned.into_inner(), false))),
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for CriticalMutex<T> {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "CriticalMutex: {:?}", self.critical)
+    }
+}
+
+impl<T> From<PoisonError<T>> for TryLockError<T> {
+    fn from(err: PoisonError<T>) -> Self {
+        TryLockError::Poisoned(err)
+    }
+}
+
+impl<T> fmt::Debug for TryLockError<T> {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        match self {
+            TryLockError::Poisoned(..) => write!(out, "Poisoned(..)"),
+            TryLockError::WouldBlock => write!(out, "WouldBlock"),
+        }
+    }
+}
+
+impl<T> Display for TryLockError<T> {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        match self {
+            TryLockError::Poisoned(err) => Display::fmt(err, out),
+            TryLockError::WouldBlock => {
+                write!(out, "try_lock failed because the operation would block")
+            }
+        }
+    }
+}
+
+impl<T> Error for TryLockError<T> {}
+
+impl<T: ?Sized> CriticalMutexGuard<'_, T> {
+    /// Returns true if a thread panicked while holding this mutex. This is an associated
+    /// function so as not to shadow methods on `T`.
+    pub fn is_poisoned(guard: &Self) -> bool {
+        guard.entered.is_poisoned()
+    }
+    /// Clears the poison flag. This is an associated function so as not to shadow
+    /// methods on `T`.
+    pub fn clear_poison(guard: &Self) {
+        guard.entered.clear_poison()
+    }
+}
+
+impl<'m, T: ?Sized> CriticalMutexGuard<'m, T> {
+    // Hands the entered critical section to a condition variable and rebuilds the guard.
+    #[cfg(feature = "CriticalCondvar")]
+    fn wait_on(
+        self,
+        wait: impl FnOnce(EnteredCritical<'m>) -> (EnteredCritical<'m>, bool),
+    ) -> (Self, bool) {
+        let Self { entered, data } = self;
+        let (entered, timed_out) = wait(entered);
+        (Self { entered, data }, timed_out)
+    }
+    fn into_result(self) -> LockResult<Self> {
+        if self.entered.is_poisoned() {
+            Err(PoisonError::new(self))
+        } else {
+            Ok(self)
+        }
+    }
+    /// Makes a guard for a part of the locked data, such as a single field. The mutex stays
+    /// locked until the returned guard is dropped. If the closure panics, the mutex is
+    /// unlocked and poisoned.
+    ///
+    /// This is an associated function so as not to shadow methods on `T`.
+    pub fn map<U: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedCriticalMutexGuard<'m, U> {
+        let Self { entered, data } = guard;
+        // Safety: we are entered, and re-entry is rejected in lock()
+        let data = NonNull::from(f(unsafe { &mut *data.get() }));
+        MappedCriticalMutexGuard {
+            entered,
+            data,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'m, U: ?Sized> MappedCriticalMutexGuard<'m, U> {
+    /// Makes a guard for a part of the already mapped data. This is an associated function
+    /// so as not to shadow methods on `U`.
+    pub fn map<V: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&mut U) -> &mut V,
+    ) -> MappedCriticalMutexGuard<'m, V> {
+        let Self {
+            entered, mut data, ..
+        } = guard;
+        // Safety: we are entered, and data was derived from the locked data
+        let data = NonNull::from(f(unsafe { data.as_mut() }));
+        MappedCriticalMutexGuard {
+            entered,
+            data,
+            _marker: PhantomData,
+        }
+    }
+    /// Returns true if a thread panicked while holding this mutex. This is an associated
+    /// function so as not to shadow methods on `U`.
+    pub fn is_poisoned(guard: &Self) -> bool {
+        guard.entered.is_poisoned()
+    }
+    /// Clears the poison flag. This is an associated function so as not to shadow
+    /// methods on `U`.
+    pub fn clear_poison(guard: &Self) {
+        guard.entered.clear_poison()
+    }
+}
+
+impl<U: ?Sized> Deref for MappedCriticalMutexGuard<'_, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        // Safety: we are entered, and data was derived from the locked data
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<U: ?Sized> DerefMut for MappedCriticalMutexGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        // Safety: we are entered, and data was derived from the locked data
+        unsafe { self.data.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Deref for CriticalMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: we are entered, and re-entry is rejected in lock()
+        unsafe { &*self.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for CriticalMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: we are entered, and re-entry is rejected in lock()
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CriticalMutex, CriticalMutexGuard, MappedCriticalMutexGuard, TryLockError};
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{mpsc, Arc, PoisonError},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn threads_on_the_wall() {
+        let mut handles = Vec::with_capacity(99);
+        let mutex = Arc::new(CriticalMutex::new(0usize));
+        for i in 0..99 {
+            let mutex = mutex.clone();
+            handles.push(thread::spawn(move || {
+                let mut x = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+                if i == 0 {
+                    panic!("Take one down")
+                }
+                let next = 1 + *x;
+                thread::yield_now();
+                *x = next;
+            }));
+        }
+        for (i, handle) in handles.into_iter().enumerate() {
+            if i == 0 {
+                handle.join().unwrap_err();
+            } else {
+                handle.join().unwrap();
+            }
+        }
+        assert_eq!(cfg!(not(feature = "no-poison")), mutex.lock().is_err());
+        let mutex = Arc::try_unwrap(mutex).unwrap();
+        assert_eq!(98, mutex.into_inner());
+    }
+
+    #[test]
+    fn try_lock() {
+        let mutex = Arc::new(CriticalMutex::new(0));
+        let guard = mutex.try_lock().unwrap();
+        assert!(matches!(mutex.try_lock(), Err(TryLockError::WouldBlock)));
+        let m = mutex.clone();
+        thread::spawn(move || assert!(matches!(m.try_lock(), Err(TryLockError::WouldBlock))))
+            .join()
+            .unwrap();
+        drop(guard);
+        #[cfg(not(feature = "no-poison"))]
+        {
+            let m = mutex.clone();
+            thread::spawn(move || {
+                let _guard = m.lock();
+                panic!("poison it")
+            })
+            .join()
+            .unwrap_err();
+            assert!(mutex.is_poisoned());
+            match mutex.try_lock() {
+                Err(TryLockError::Poisoned(poisoned)) => *poisoned.into_inner() += 1,
+                _ => panic!("expected Poisoned"),
+            }
+            mutex.clear_poison();
+            assert!(!mutex.is_poisoned());
+        }
+        assert!(mutex.try_lock().is_ok());
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut mutex = CriticalMutex::new(1);
+        *mutex.get_mut() += 1;
+        assert_eq!(2, *mutex.lock().unwrap());
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-poison"))]
+    fn poison_err() {
+        let mutex = Arc::new(CriticalMutex::new(0));
+        let m = mutex.clone();
+        thread::spawn(move || {
+            let mut guard = m.lock().unwrap();
+            *guard += 1;
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        let guard = match mutex.lock() {
+            Ok(_) => panic!("Expected poison"),
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        assert_eq!(1, *guard);
+        CriticalMutexGuard::clear_poison(&guard);
+        drop(guard);
+        assert!(mutex.lock().is_ok());
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-poison"))]
+    fn recover_with() {
+        let mutex = Arc::new(CriticalMutex::new((0, 0)));
+        let m = mutex.clone();
+        thread::spawn(move || {
+            let mut pair = m.lock().unwrap();
+            pair.0 += 1;
+            panic!("Poison it before the second half")
+        })
+        .join()
+        .unwrap_err();
+        let m = mutex.clone();
+        thread::spawn(move || {
+            let pair = m.recover_with(|pair| pair.1 = pair.0);
+            assert_eq!((1, 1), *pair);
+        })
+        .join()
+        .unwrap();
+        assert!(mutex.lock().is_ok());
+        let pair = mutex.recover_with(|_| panic!("Not poisoned"));
+        assert_eq!(pair.0, pair.1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn relock_panics() {
+        let mutex = CriticalMutex::new(());
+        let _outer = mutex.lock();
+        let _inner = mutex.lock();
+    }
+
+    #[test]
+    fn map() {
+        struct Network {
+            port: u16,
+        }
+        struct Config {
+            name: &'static str,
+            network: Network,
+        }
+        let mutex = CriticalMutex::new(Config {
+            name: "config",
+            network: Network { port: 80 },
+        });
+        let guard = mutex.lock().unwrap();
+        let mut network = CriticalMutexGuard::map(guard, |config| &mut config.network);
+        network.port = 8080;
+        let mut port = MappedCriticalMutexGuard::map(network, |network| &mut network.port);
+        *port += 1;
+        drop(port);
+        let config = mutex.lock().unwrap();
+        assert_eq!("config", config.name);
+        assert_eq!(8081, config.network.port);
+    }
+
+    #[test]
+    fn map_panic_poisons() {
+        let mutex = CriticalMutex::new((0, 0));
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let guard = mutex.lock().unwrap();
+            CriticalMutexGuard::map(guard, |_| -> &mut i32 { panic!("Poison it") });
+        }))
+        .unwrap_err();
+        assert_eq!(cfg!(not(feature = "no-poison")), mutex.lock().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "CriticalCondvar")]
+    fn wait_while() {
+        use crate::CriticalCondvar;
+        use std::collections::VecDeque;
+        let queue = Arc::new((CriticalMutex::new(VecDeque::new()), CriticalCondvar::new()));
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    let (mutex, not_empty) = &*queue;
+                    let mut sum = 0;
+                    for _ in 0..250 {
+                        let mut items = not_empty
+                            .wait_while(mutex.lock().unwrap(), |items| items.is_empty())
+                            .unwrap();
+                        sum += items.pop_front().unwrap();
+                    }
+                    sum
+                })
+            })
+            .collect();
+        let (mutex, not_empty) = &*queue;
+        for i in 0..1000 {
+            mutex.lock().unwrap().push_back(i);
+            not_empty.notify_one();
+        }
+        let sum: usize = consumers.into_iter().map(|c| c.join().unwrap()).sum();
+        assert_eq!((0..1000).sum::<usize>(), sum);
+    }
+
+    #[test]
+    #[cfg(feature = "CriticalCondvar")]
+    fn wait_timeout_while() {
+        use crate::CriticalCondvar;
+        let condvar = CriticalCondvar::new();
+        let mutex = CriticalMutex::new(0);
+        let start = Instant::now();
+        let (guard, timed_out) = condvar
+            .wait_timeout_while(mutex.lock().unwrap(), Duration::from_millis(50), |n| {
+                *n == 0
+            })
+            .unwrap();
+        assert!(timed_out);
+        assert!(start.elapsed() >= Duration::from_millis(45));
+        drop(guard);
+        let (guard, timed_out) = condvar
+            .wait_timeout_while(mutex.lock().unwrap(), Duration::from_secs(10), |n| {
+                *n += 1;
+                *n < 3
+            })
+            .unwrap();
+        assert!(!timed_out);
+        drop(guard);
+        let (guard, timed_out) = condvar
+            .wait_timeout_while(mutex.lock().unwrap(), Duration::MAX, |_| false)
+            .unwrap();
+        assert!(!timed_out);
+        assert_eq!(3, *guard);
+    }
+
+    #[test]
+    fn try_lock_for() {
+        let mutex = Arc::new(CriticalMutex::new(0));
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let m = mutex.clone();
+        let handle = thread::spawn(move || {
+            let mut guard = m.lock().unwrap();
+            locked_tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(100));
+            *guard += 1;
+        });
+        locked_rx.recv().unwrap();
+        assert!(mutex.try_lock_for(Duration::from_millis(10)).is_none());
+        let guard = mutex
+            .try_lock_until(Instant::now() + Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, *guard);
+        drop(guard);
+        handle.join().unwrap();
+    }
+}
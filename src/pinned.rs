@@ -0,0 +1,207 @@
+// This module should not use std.
+
+use crate::common::{PoisonableCriticalSection, POISONABLE_ZEROED};
+use crate::lazy_init::{run_init_once, INITIALIZED, UNINITIALIZED};
+use crate::{EnteredCritical, SpinCount};
+
+use crate::wrapper::{
+    delete_cs, enter_cs, init_cs, init_cs_with_spin_count, set_cs_spin_count, try_enter_cs,
+    CRITICAL_SECTION,
+};
+
+use core::{
+    fmt::{self, Formatter},
+    marker::PhantomPinned,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// PinnedCriticalSection stores its CRITICAL_SECTION inline rather than behind an Arc, for
+/// embedding in a struct which is itself already shared, such as through the owner's own Arc.
+/// This saves an allocation and a reference count per critical section.
+///
+/// A CRITICAL_SECTION may not move once initialized, so every operation takes `Pin<&Self>`.
+/// Since a value cannot be pinned before it is returned from new(), initialization happens
+/// on first use instead, and the critical section is deleted on drop if it was ever used.
+///
+/// Unlike CriticalSection, this cannot be cloned, is not tracked by the deadlock-detection
+/// feature, and EnteredCritical::section() returns None for it.
+pub struct PinnedCriticalSection {
+    init_spin_count: Option<u32>,
+    init: AtomicUsize,
+    inner: PoisonableCriticalSection,
+    _pinned: PhantomPinned,
+}
+
+// Safety: Critical Section API is naturally thread-safe, and the CRITICAL_SECTION is only
+// used through Pin, so it does not move while initialized.
+unsafe impl Send for PinnedCriticalSection {}
+unsafe impl Sync for PinnedCriticalSection {}
+
+impl PinnedCriticalSection {
+    /// Creates a new PinnedCriticalSection. It is initialized on first use.
+    pub const fn new() -> Self {
+        Self {
+            init_spin_count: None,
+            init: AtomicUsize::new(UNINITIALIZED),
+            inner: POISONABLE_ZEROED,
+            _pinned: PhantomPinned,
+        }
+    }
+    /// Creates a new PinnedCriticalSection which will be initialized with the provided
+    /// spin_count. Being a const fn, this takes a u32, which is clamped as by SpinCount::new().
+    pub const fn with_spin_count(spin_count: u32) -> Self {
+        Self {
+            init_spin_count: Some(SpinCount::new(spin_count).get()),
+            init: AtomicUsize::new(UNINITIALIZED),
+            inner: POISONABLE_ZEROED,
+            _pinned: PhantomPinned,
+        }
+    }
+    fn init_once(self: Pin<&Self>) {
+        let ptr = self.lpCriticalSection();
+        let spin_count = self.init_spin_count;
+        // Safety: run_init_once() calls this at most once, before anything else uses it, and
+        // self is pinned so it will not move.
+        run_init_once(&self.init, || unsafe {
+            match spin_count {
+                Some(spin_count) => init_cs_with_spin_count(ptr, spin_count),
+                None => init_cs(ptr),
+            }
+        })
+    }
+    #[allow(non_snake_case)]
+    fn lpCriticalSection(&self) -> *mut CRITICAL_SECTION {
+        self.inner.critical.get()
+    }
+    /// Enters the critical section, blocking until it is available. This will not deadlock
+    /// if the calling thread is already in the critical section.
+    pub fn enter(self: Pin<&Self>) -> EnteredCritical<'_> {
+        self.init_once();
+        let this = self.get_ref();
+        // Safety: no return value. Naturally thread-safe.
+        unsafe {
+            enter_cs(this.lpCriticalSection());
+            EnteredCritical::new(&this.inner)
+        }
+    }
+    /// Tries to enter the critical section without blocking. This will not deadlock if the
+    /// calling thread is already in the critical section.
+    pub fn try_enter(self: Pin<&Self>) -> Option<EnteredCritical<'_>> {
+        self.init_once();
+        let this = self.get_ref();
+        // Safety: returns non-zero if we are in critical section when call returns.
+        // Naturally thread-safe.
+        unsafe {
+            match try_enter_cs(this.lpCriticalSection()) {
+                0 => {
+                    this.inner.record_try_enter_failure();
+                    None
+                }
+                _ => Some(EnteredCritical::new(&this.inner)),
+            }
+        }
+    }
+    /// Sets the spin count of this critical section, and returns the old value.
+    pub fn set_spin_count(self: Pin<&Self>, spin_count: impl Into<SpinCount>) -> u32 {
+        self.init_once();
+        // Safety: cannot fail. Returns previous spin_count. Naturally thread-safe.
+        unsafe { set_cs_spin_count(self.lpCriticalSection(), spin_count.into().get()) }
+    }
+    /// Returns the current spin count of this critical section.
+    pub fn spin_count(self: Pin<&Self>) -> u32 {
+        self.init_once();
+        self.inner.spin_count()
+    }
+    /// Returns true if a thread panicked while inside this critical section. The result
+    /// may be stale as soon as it is returned.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+    /// Returns true if any thread currently holds this critical section, without
+    /// attempting to enter it. This is a best-effort snapshot which may be stale as soon
+    /// as it is returned, and is intended for monitoring rather than synchronization.
+    pub fn is_locked(&self) -> bool {
+        INITIALIZED == self.init.load(Ordering::Acquire) && self.inner.is_locked()
+    }
+}
+
+impl Default for PinnedCriticalSection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PinnedCriticalSection {
+    fn drop(&mut self) {
+        if INITIALIZED == *self.init.get_mut() {
+            // Safety: we have exclusive access, it was initialized, and FFI call never fails
+            unsafe { delete_cs(self.lpCriticalSection()) }
+        }
+    }
+}
+
+impl fmt::Debug for PinnedCriticalSection {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "PinnedCriticalSection: {:p}", self.lpCriticalSection())?;
+        if self.is_poisoned() {
+            write!(out, " (poisoned)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PinnedCriticalSection;
+    use std::{
+        pin::{pin, Pin},
+        sync::Arc,
+        thread,
+    };
+
+    #[test]
+    fn inline_in_arc() {
+        struct Entity {
+            critical: PinnedCriticalSection,
+            id: u32,
+        }
+        let entity = Arc::new(Entity {
+            critical: PinnedCriticalSection::with_spin_count(100),
+            id: 7,
+        });
+        // Safety: the Entity never moves out of its Arc
+        let critical = unsafe { Pin::new_unchecked(&entity.critical) };
+        assert_eq!(100, critical.spin_count());
+        let entered = critical.enter();
+        assert!(critical.try_enter().is_some());
+        assert!(critical.is_locked());
+        #[cfg(feature = "CriticalSection")]
+        assert!(entered.section().is_none());
+        let e = entity.clone();
+        thread::spawn(move || {
+            let critical = unsafe { Pin::new_unchecked(&e.critical) };
+            assert!(critical.try_enter().is_none());
+        })
+        .join()
+        .unwrap();
+        drop(entered);
+        assert_eq!(7, entity.id);
+    }
+
+    #[test]
+    fn stack_pinned() {
+        let critical = pin!(PinnedCriticalSection::new());
+        let critical = critical.as_ref();
+        assert_eq!(0, critical.set_spin_count(50));
+        assert_eq!(50, critical.spin_count());
+        critical.enter().leave();
+    }
+
+    #[test]
+    fn drop_uninitialized() {
+        let critical = PinnedCriticalSection::new();
+        assert!(!critical.is_locked());
+        drop(critical);
+    }
+}
@@ -0,0 +1,98 @@
+// This module should not use std.
+
+//! Low-level access to a CRITICAL_SECTION, without guards or poisoning.
+
+use crate::wrapper::{enter_cs, leave_cs, set_cs_spin_count, try_enter_cs, CRITICAL_SECTION};
+use crate::SpinCount;
+
+/// A thin, Copy handle to an initialized CRITICAL_SECTION owned elsewhere, exposing the
+/// underlying operations directly. There is no RAII guard and no poison flag, so the caller
+/// is responsible for pairing every successful enter with a leave on the same thread.
+///
+/// This is the building block for custom guards. The invariants the caller must uphold are:
+///
+/// - The CRITICAL_SECTION is initialized before the handle is created, and is not deleted,
+///   moved, or freed while this handle or any copy of it may still be used.
+/// - leave() is only called by a thread which has entered and not yet left, exactly once
+///   per successful enter() or try_enter().
+/// - Only threads of the process which initialized the CRITICAL_SECTION use it, even if it
+///   is in memory shared with another process. See Safety.md.
+/// - A thread does not exit while it is entered. Unlike the guards elsewhere in this crate,
+///   nothing leaves on unwind, so the caller must arrange for that if needed.
+///
+/// Violating the first two leaves the critical section in an undefined state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RawCriticalSection(*mut CRITICAL_SECTION);
+
+// Safety: the Critical Section API is naturally thread-safe, and from_ptr() requires the
+// CRITICAL_SECTION to outlive every use of the handle.
+unsafe impl Send for RawCriticalSection {}
+unsafe impl Sync for RawCriticalSection {}
+
+impl RawCriticalSection {
+    /// Creates a handle to the CRITICAL_SECTION at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an initialized CRITICAL_SECTION which stays initialized and in
+    /// place for as long as this handle, or any copy of it, is used.
+    pub const unsafe fn from_ptr(ptr: *mut CRITICAL_SECTION) -> Self {
+        Self(ptr)
+    }
+    /// Returns the pointer this handle was created with.
+    pub fn as_ptr(self) -> *mut CRITICAL_SECTION {
+        self.0
+    }
+    /// Enters the critical section, blocking until it is available. Recursive entry by the
+    /// same thread succeeds immediately, and must be matched by another leave().
+    pub fn enter(self) {
+        // Safety: from_ptr() requires an initialized CRITICAL_SECTION. No return value.
+        unsafe { enter_cs(self.0) }
+    }
+    /// Tries to enter the critical section without blocking, returning true if it was
+    /// entered.
+    pub fn try_enter(self) -> bool {
+        // Safety: from_ptr() requires an initialized CRITICAL_SECTION. Returns non-zero if
+        // we are in critical section when call returns.
+        unsafe { try_enter_cs(self.0) != 0 }
+    }
+    /// Leaves the critical section once.
+    ///
+    /// # Safety
+    ///
+    /// The calling thread must currently be entered, and each enter may only be left once.
+    pub unsafe fn leave(self) {
+        leave_cs(self.0)
+    }
+    /// Sets the spin count, returning the previous value.
+    pub fn set_spin_count(self, spin_count: impl Into<SpinCount>) -> u32 {
+        // Safety: from_ptr() requires an initialized CRITICAL_SECTION. Cannot fail.
+        unsafe { set_cs_spin_count(self.0, spin_count.into().get()) }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "CriticalSection")]
+mod tests {
+    use crate::{raw::RawCriticalSection, CriticalSection};
+    use std::thread;
+
+    #[test]
+    fn custom_guard() {
+        let critical = CriticalSection::new();
+        let raw = unsafe { RawCriticalSection::from_ptr(critical.as_raw()) };
+        raw.enter();
+        assert!(raw.try_enter());
+        assert!(critical.is_locked());
+        thread::scope(|s| {
+            s.spawn(|| assert!(!raw.try_enter())).join().unwrap();
+        });
+        unsafe {
+            raw.leave();
+            raw.leave();
+        }
+        assert!(!critical.is_locked());
+        assert_eq!(0, raw.set_spin_count(100));
+        assert_eq!(100, critical.spin_count());
+    }
+}
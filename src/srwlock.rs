@@ -0,0 +1,287 @@
+// This module should not use std, other than to detect panics for poisoning.
+
+use crate::wrapper::{
+    acquire_srw_exclusive, acquire_srw_shared, release_srw_exclusive, release_srw_shared,
+    try_acquire_srw_exclusive, try_acquire_srw_shared, SRWLOCK, SRWLOCK_INIT,
+};
+
+use static_assertions::assert_not_impl_all;
+
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Formatter},
+    mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// SlimRwLock is a reader-writer lock over a Windows SRWLOCK. Any number of threads may hold
+/// it for reading at once, or a single thread for writing, so readers are not serialized
+/// the way they are by a critical section.
+///
+/// Unlike a critical section, an SRWLOCK is not recursive. Acquiring it for writing while
+/// the same thread already holds it in any mode deadlocks, and so may acquiring it for
+/// reading again while a writer is waiting. It needs no initialization or deletion, so it
+/// can be constructed statically.
+///
+/// Like EnteredCritical, a write guard dropped while its thread is panicking poisons the
+/// lock. Read guards cannot modify anything, so they never poison it.
+pub struct SlimRwLock {
+    inner: UnsafeCell<SRWLOCK>,
+    // Only written while held for writing, but may be read at any time.
+    poison: AtomicBool,
+}
+
+// Safety: SRW Lock API is naturally thread-safe. An SRWLOCK may be moved so long as no
+// thread holds it, which the borrow checker guarantees.
+unsafe impl Send for SlimRwLock {}
+unsafe impl Sync for SlimRwLock {}
+
+/// Shared access to a SlimRwLock, released when dropped.
+pub struct SlimReadGuard<'l>(&'l SlimRwLock);
+/// Exclusive access to a SlimRwLock, released when dropped.
+pub struct SlimWriteGuard<'l>(&'l SlimRwLock);
+
+// Safety: an SRWLOCK must be released by the thread which acquired it.
+assert_not_impl_all!(SlimReadGuard: Send, Sync, Copy, Clone);
+assert_not_impl_all!(SlimWriteGuard: Send, Sync, Copy, Clone);
+
+impl SlimRwLock {
+    /// Creates a new SlimRwLock.
+    pub const fn new() -> Self {
+        Self {
+            inner: UnsafeCell::new(SRWLOCK_INIT),
+            poison: AtomicBool::new(false),
+        }
+    }
+    /// Acquires the lock for reading, blocking until no thread holds it for writing.
+    pub fn read(&self) -> SlimReadGuard<'_> {
+        // Safety: no return value. Naturally thread-safe.
+        unsafe { acquire_srw_shared(self.inner.get()) }
+        SlimReadGuard(self)
+    }
+    /// Tries to acquire the lock for reading without blocking.
+    pub fn try_read(&self) -> Option<SlimReadGuard<'_>> {
+        // Safety: returns whether the lock was acquired. Naturally thread-safe.
+        match unsafe { try_acquire_srw_shared(self.inner.get()) } {
+            true => Some(SlimReadGuard(self)),
+            false => None,
+        }
+    }
+    /// Acquires the lock for writing, blocking until no other thread holds it.
+    pub fn write(&self) -> SlimWriteGuard<'_> {
+        // Safety: no return value. Naturally thread-safe.
+        unsafe { acquire_srw_exclusive(self.inner.get()) }
+        SlimWriteGuard(self)
+    }
+    /// Tries to acquire the lock for writing without blocking.
+    pub fn try_write(&self) -> Option<SlimWriteGuard<'_>> {
+        // Safety: returns whether the lock was acquired. Naturally thread-safe.
+        match unsafe { try_acquire_srw_exclusive(self.inner.get()) } {
+            true => Some(SlimWriteGuard(self)),
+            false => None,
+        }
+    }
+    /// Returns true if a thread panicked while holding this lock for writing. Unless the
+    /// calling thread holds the lock, another thread may poison it or clear the poison at
+    /// any moment after this returns.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.load(Ordering::Relaxed)
+    }
+    /// Acquires the lock for writing, clears the poison flag, and releases it.
+    pub fn clear_poison(&self) {
+        self.write().clear_poison()
+    }
+}
+
+impl Default for SlimRwLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SlimRwLock {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "SlimRwLock: {:p}", self.inner.get())?;
+        if self.is_poisoned() {
+            write!(out, " (poisoned)")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'l> SlimReadGuard<'l> {
+    pub fn release(self) {
+        drop(self)
+    }
+    pub fn is_poisoned(&self) -> bool {
+        self.0.is_poisoned()
+    }
+    /// Tries to exchange this read guard for a write guard, returning the read guard again
+    /// if another thread holds the lock.
+    ///
+    /// SRWLOCK cannot upgrade atomically, so this releases the shared hold before trying to
+    /// acquire an exclusive one. Another writer may run in that gap, so anything read before
+    /// upgrading must be re-checked afterwards. On failure the lock is acquired for reading
+    /// again, which may block briefly if a writer got in first.
+    pub fn try_upgrade(self) -> Result<SlimWriteGuard<'l>, SlimReadGuard<'l>> {
+        let lock = self.0;
+        mem::forget(self);
+        // Safety: we held the lock for reading, and forgot the guard so it is released once.
+        // try_acquire_srw_exclusive returns whether the lock was acquired.
+        unsafe {
+            release_srw_shared(lock.inner.get());
+            if try_acquire_srw_exclusive(lock.inner.get()) {
+                return Ok(SlimWriteGuard(lock));
+            }
+        }
+        Err(lock.read())
+    }
+}
+
+impl<'l> SlimWriteGuard<'l> {
+    pub fn release(self) {
+        drop(self)
+    }
+    /// Exchanges this write guard for a read guard, so other readers can proceed.
+    ///
+    /// SRWLOCK cannot downgrade atomically, so this releases the exclusive hold and then
+    /// acquires a shared one. Another writer may run in that gap, so state built while
+    /// writing is not guaranteed to be unchanged once the read guard is returned. Where that
+    /// matters, re-check it, or keep the write guard instead.
+    pub fn downgrade(self) -> SlimReadGuard<'l> {
+        let lock = self.0;
+        mem::forget(self);
+        // Safety: we held the lock for writing, and forgot the guard so it is released once.
+        unsafe { release_srw_exclusive(lock.inner.get()) }
+        lock.read()
+    }
+    pub fn is_poisoned(&self) -> bool {
+        self.0.is_poisoned()
+    }
+    pub fn clear_poison(&self) {
+        // Only write poison value while held for writing
+        self.0.poison.store(false, Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for SlimReadGuard<'_> {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "SlimReadGuard: {:?}", self.0)
+    }
+}
+
+impl fmt::Debug for SlimWriteGuard<'_> {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "SlimWriteGuard: {:?}", self.0)
+    }
+}
+
+impl Drop for SlimReadGuard<'_> {
+    fn drop(&mut self) {
+        // Safety: cannot fail, no return value, release exactly once.
+        unsafe { release_srw_shared(self.0.inner.get()) }
+    }
+}
+
+impl Drop for SlimWriteGuard<'_> {
+    fn drop(&mut self) {
+        #[cfg(feature = "std")]
+        if std::thread::panicking() {
+            // Only write poison value while held for writing
+            self.0.poison.store(true, Ordering::Relaxed)
+        }
+        // Safety: cannot fail, no return value, release exactly once.
+        unsafe { release_srw_exclusive(self.0.inner.get()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SlimRwLock;
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+    };
+
+    #[test]
+    fn shared_readers() {
+        let lock = Arc::new(SlimRwLock::new());
+        let reading = lock.read();
+        let l = lock.clone();
+        thread::spawn(move || {
+            assert!(l.try_read().is_some());
+            assert!(l.try_write().is_none());
+        })
+        .join()
+        .unwrap();
+        reading.release();
+        let writing = lock.write();
+        let l = lock.clone();
+        thread::spawn(move || assert!(l.try_read().is_none()))
+            .join()
+            .unwrap();
+        drop(writing);
+    }
+
+    #[test]
+    fn writer_waits() {
+        static LOCK: SlimRwLock = SlimRwLock::new();
+        static mut X: usize = 0;
+        let (read_tx, read_rx) = mpsc::channel();
+        let reading = LOCK.read();
+        let handle = thread::spawn(move || {
+            read_tx.send(()).unwrap();
+            let _writing = LOCK.write();
+            unsafe { X += 1 }
+        });
+        read_rx.recv().unwrap();
+        assert_eq!(0, unsafe { X });
+        drop(reading);
+        handle.join().unwrap();
+        assert_eq!(1, unsafe { X });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn poison_on_write_only() {
+        let lock = Arc::new(SlimRwLock::new());
+        let l = lock.clone();
+        thread::spawn(move || {
+            let _reading = l.read();
+            panic!("Reading cannot poison")
+        })
+        .join()
+        .unwrap_err();
+        assert!(!lock.is_poisoned());
+        let l = lock.clone();
+        thread::spawn(move || {
+            let _writing = l.write();
+            panic!("Poison it")
+        })
+        .join()
+        .unwrap_err();
+        assert!(lock.is_poisoned());
+        assert!(lock.read().is_poisoned());
+        lock.clear_poison();
+        assert!(!lock.is_poisoned());
+    }
+
+    #[test]
+    fn downgrade_upgrade() {
+        let lock = Arc::new(SlimRwLock::new());
+        let reading = lock.write().downgrade();
+        assert!(lock.try_read().is_some());
+        assert!(lock.try_write().is_none());
+        let other = lock.read();
+        // Another reader blocks the upgrade
+        let reading = reading.try_upgrade().unwrap_err();
+        drop(other);
+        let writing = reading.try_upgrade().unwrap();
+        let l = lock.clone();
+        thread::spawn(move || assert!(l.try_read().is_none()))
+            .join()
+            .unwrap();
+        drop(writing);
+        assert!(lock.try_write().is_some());
+    }
+}
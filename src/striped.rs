@@ -0,0 +1,133 @@
+// This module should not use std.
+
+use crate::{CriticalStatic, EnteredCritical};
+
+use core::hash::{Hash, Hasher};
+
+/// A fixed number of CriticalStatics, each guarding a stripe of some shared structure, such
+/// as the buckets of a hash map. Keys are hashed to pick a stripe, so threads working on
+/// different keys usually do not contend with each other.
+///
+/// Keys which hash to the same stripe simply share its critical section. This is always
+/// correct, but those keys contend as if there was a single lock. Since every stripe is a
+/// separate critical section, entering the stripes of two keys at once may deadlock against
+/// another thread doing the same in the other order, unless they happen to share a stripe.
+///
+/// Each stripe is initialized lazily on first use, like any CriticalStatic.
+///
+/// Stripes are entered with enter_for() and enter_index(), rather than lock_* methods, since
+/// like CriticalStatic::enter() they return an EnteredCritical. Only CriticalMutex, which
+/// guards data as std's Mutex does, is locked.
+pub struct StripedCritical<const N: usize> {
+    stripes: [CriticalStatic; N],
+}
+
+impl<const N: usize> StripedCritical<N> {
+    /// Creates a new StripedCritical with N stripes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if N is 0. In a static, this fails to compile instead.
+    pub const fn new() -> Self {
+        assert!(N > 0, "StripedCritical needs at least one stripe");
+        Self {
+            stripes: [const { CriticalStatic::new() }; N],
+        }
+    }
+    /// Returns the number of stripes, N.
+    pub const fn len(&self) -> usize {
+        N
+    }
+    /// Always false, since there is at least one stripe.
+    pub const fn is_empty(&self) -> bool {
+        false
+    }
+    /// Returns the index of the stripe the key hashes to. The hash is the same on every
+    /// run, and for every StripedCritical with the same N.
+    pub fn stripe_index(&self, key: &impl Hash) -> usize {
+        let mut hasher = FnvHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % N as u64) as usize
+    }
+    /// Enters the stripe the key hashes to.
+    pub fn enter_for(&'static self, key: &impl Hash) -> EnteredCritical<'static> {
+        self.stripes[self.stripe_index(key)].enter()
+    }
+    /// Enters the stripe at the provided index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if index is not less than N.
+    pub fn enter_index(&'static self, index: usize) -> EnteredCritical<'static> {
+        self.stripes[index].enter()
+    }
+    /// Returns the CriticalStatic for the stripe at the provided index, or None if index is
+    /// not less than N.
+    pub fn get(&self, index: usize) -> Option<&CriticalStatic> {
+        self.stripes.get(index)
+    }
+}
+
+impl<const N: usize> Default for StripedCritical<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// FNV-1a, since core has no hasher of its own, and this needs neither keys nor DoS
+// resistance. Stripes are only ever picked for keys the program chose to lock.
+struct FnvHasher(u64);
+
+impl FnvHasher {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::StripedCritical;
+    use std::thread;
+
+    #[test]
+    fn stripes() {
+        static STRIPED: StripedCritical<8> = StripedCritical::new();
+        assert_eq!(8, STRIPED.len());
+        let index = STRIPED.stripe_index(&"key");
+        assert!(index < 8);
+        assert_eq!(index, STRIPED.stripe_index(&"key"));
+        let entered = STRIPED.enter_for(&"key");
+        let other = (index + 1) % 8;
+        thread::spawn(move || {
+            assert!(STRIPED.get(index).unwrap().try_enter().is_none());
+            assert!(STRIPED.get(other).unwrap().try_enter().is_some());
+            STRIPED.enter_index(other).leave();
+        })
+        .join()
+        .unwrap();
+        drop(entered);
+        assert!(STRIPED.get(8).is_none());
+    }
+
+    #[test]
+    fn spread() {
+        static STRIPED: StripedCritical<16> = StripedCritical::new();
+        let mut used = [false; 16];
+        for key in 0..256u32 {
+            used[STRIPED.stripe_index(&key)] = true;
+        }
+        assert!(used.iter().all(|&used| used));
+    }
+}
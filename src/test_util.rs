@@ -0,0 +1,43 @@
+// This module should not use std.
+
+//! Helpers for testing code which takes an EnteredCritical as proof that a lock is held,
+//! without setting up the CriticalSection it would normally come from.
+
+use crate::{CriticalSection, EnteredCritical};
+
+use alloc::boxed::Box;
+
+/// Creates a throwaway CriticalSection and runs the provided closure with it entered. The
+/// critical section is left and deleted when the closure returns.
+pub fn with_entered<R>(f: impl FnOnce(EnteredCritical<'_>) -> R) -> R {
+    let critical = CriticalSection::new();
+    f(critical.enter_unchecked())
+}
+
+/// Returns an EnteredCritical for a new, throwaway CriticalSection, for tests which need a
+/// guard with no lifetime to keep track of.
+///
+/// The critical section is leaked, so each call leaks a small allocation. This is meant
+/// for tests, not for use in a loop.
+pub fn entered() -> EnteredCritical<'static> {
+    Box::leak(Box::new(CriticalSection::new())).enter_unchecked()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{entered, with_entered};
+    use crate::EnteredCritical;
+
+    fn needs_lock(entered: EnteredCritical<'_>) -> i32 {
+        entered.recursion_count()
+    }
+
+    #[test]
+    fn guard_proof() {
+        assert_eq!(1, with_entered(needs_lock));
+        assert_eq!(1, needs_lock(entered()));
+        let first = entered();
+        let second = entered();
+        assert_ne!(first.id(), second.id());
+    }
+}
@@ -1,53 +1,575 @@
-use winapi::{shared::minwindef::DWORD, um::minwinbase::LPCRITICAL_SECTION};
+use static_assertions::const_assert_eq;
 
-#[link(name = "wrapper", kind = "static")]
-extern "C" {
-    fn _c_init_cs(lpCriticalSection: LPCRITICAL_SECTION) -> DWORD;
-    fn _c_init_cs_with_spin_count(
-        lpCriticalSection: LPCRITICAL_SECTION,
-        spin_count: DWORD,
-    ) -> DWORD;
-    fn _c_enter_cs(lpCriticalSection: LPCRITICAL_SECTION) -> DWORD;
-    fn _c_try_enter_cs(lpCriticalSection: LPCRITICAL_SECTION) -> DWORD;
-    fn _c_leave_cs(lpCriticalSection: LPCRITICAL_SECTION);
-    fn _c_delete_cs(lpCriticalSection: LPCRITICAL_SECTION);
-    fn _c_set_cs_spin_count(lpCriticalSection: LPCRITICAL_SECTION, spin_count: DWORD) -> DWORD;
-}
+use core::{
+    mem,
+    ptr::addr_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+#[cfg(all(feature = "CriticalStatic", windows, not(feature = "windows-sys")))]
+pub(crate) use winapi::shared::winerror::ERROR_ALREADY_INITIALIZED;
+#[cfg(all(feature = "CriticalSection", windows, not(feature = "windows-sys")))]
+pub(crate) use winapi::shared::winerror::ERROR_BUSY;
+#[cfg(all(
+    any(feature = "CriticalSection", feature = "CriticalStatic"),
+    windows,
+    not(feature = "windows-sys")
+))]
+use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
+#[cfg(all(
+    any(
+        feature = "CriticalSection",
+        feature = "spin-fallback",
+        debug_assertions
+    ),
+    windows,
+    not(feature = "windows-sys")
+))]
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+#[cfg(all(feature = "CriticalSection", windows, not(feature = "windows-sys")))]
+use winapi::um::processthreadsapi::SwitchToThread;
+#[cfg(all(feature = "CriticalSection", windows, not(feature = "windows-sys")))]
+use winapi::um::sysinfoapi::{GetSystemInfo, SYSTEM_INFO};
+
+#[cfg(all(windows, not(feature = "windows-sys")))]
+use winapi::um::errhandlingapi::GetLastError;
+#[cfg(all(windows, not(feature = "windows-sys")))]
+use winapi::um::synchapi::{
+    DeleteCriticalSection, EnterCriticalSection, InitializeCriticalSection,
+    InitializeCriticalSectionAndSpinCount, LeaveCriticalSection, SetCriticalSectionSpinCount,
+    TryEnterCriticalSection,
+};
+#[cfg(all(feature = "CriticalCondvar", windows, not(feature = "windows-sys")))]
+use winapi::um::synchapi::{
+    SleepConditionVariableCS, WakeAllConditionVariable, WakeConditionVariable,
+};
+#[cfg(all(windows, not(feature = "windows-sys")))]
+pub(crate) use winapi::{
+    shared::minwindef::DWORD,
+    um::minwinbase::{CRITICAL_SECTION, LPCRITICAL_SECTION},
+};
+#[cfg(all(feature = "CriticalCondvar", windows, not(feature = "windows-sys")))]
+pub(crate) use winapi::{
+    shared::winerror::ERROR_TIMEOUT,
+    um::{
+        synchapi::{CONDITION_VARIABLE, CONDITION_VARIABLE_INIT, PCONDITION_VARIABLE},
+        winbase::INFINITE,
+    },
+};
+
+#[cfg(all(feature = "SlimRwLock", windows, not(feature = "windows-sys")))]
+use winapi::um::synchapi::{
+    AcquireSRWLockExclusive, AcquireSRWLockShared, ReleaseSRWLockExclusive, ReleaseSRWLockShared,
+    TryAcquireSRWLockExclusive, TryAcquireSRWLockShared,
+};
+#[cfg(all(feature = "SlimRwLock", windows, not(feature = "windows-sys")))]
+pub(crate) use winapi::um::synchapi::{PSRWLOCK, SRWLOCK, SRWLOCK_INIT};
+
+// Outside Windows, the FFI functions are stubs which panic, so the crate still builds for
+// docs and editor tooling. winapi has no types there either, so those are stubbed too.
+#[cfg(all(not(windows), not(feature = "windows-sys")))]
+pub(crate) use stubs::types::*;
+#[cfg(not(windows))]
+use stubs::*;
+
+#[cfg(not(feature = "windows-sys"))]
+pub(crate) const CRIT_ZEROED: CRITICAL_SECTION = CRITICAL_SECTION {
+    DebugInfo: core::ptr::null_mut(),
+    LockCount: 0,
+    LockSemaphore: core::ptr::null_mut(),
+    OwningThread: core::ptr::null_mut(),
+    RecursionCount: 0,
+    SpinCount: 0,
+};
+
+#[cfg(all(windows, feature = "windows-sys"))]
+use windows_sys::Win32::Foundation::GetLastError;
+#[cfg(all(feature = "CriticalStatic", feature = "windows-sys"))]
+pub(crate) use windows_sys::Win32::Foundation::ERROR_ALREADY_INITIALIZED;
+#[cfg(all(feature = "CriticalSection", feature = "windows-sys"))]
+pub(crate) use windows_sys::Win32::Foundation::ERROR_BUSY;
+#[cfg(all(
+    any(feature = "CriticalSection", feature = "CriticalStatic"),
+    windows,
+    feature = "windows-sys"
+))]
+use windows_sys::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
+#[cfg(all(feature = "CriticalSection", windows, feature = "windows-sys"))]
+use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+#[cfg(all(
+    any(
+        feature = "CriticalSection",
+        feature = "spin-fallback",
+        debug_assertions
+    ),
+    windows,
+    feature = "windows-sys"
+))]
+use windows_sys::Win32::System::Threading::GetCurrentThreadId;
+#[cfg(all(feature = "CriticalSection", windows, feature = "windows-sys"))]
+use windows_sys::Win32::System::Threading::SwitchToThread;
+#[cfg(feature = "windows-sys")]
+pub(crate) use windows_sys::Win32::System::Threading::CRITICAL_SECTION;
+#[cfg(all(feature = "SlimRwLock", feature = "windows-sys"))]
+pub(crate) use windows_sys::Win32::System::Threading::SRWLOCK;
+#[cfg(all(feature = "SlimRwLock", windows, feature = "windows-sys"))]
+use windows_sys::Win32::System::Threading::{
+    AcquireSRWLockExclusive, AcquireSRWLockShared, ReleaseSRWLockExclusive, ReleaseSRWLockShared,
+    TryAcquireSRWLockExclusive, TryAcquireSRWLockShared,
+};
+#[cfg(all(windows, feature = "windows-sys"))]
+use windows_sys::Win32::System::Threading::{
+    DeleteCriticalSection, EnterCriticalSection, InitializeCriticalSection,
+    InitializeCriticalSectionAndSpinCount, LeaveCriticalSection, SetCriticalSectionSpinCount,
+    TryEnterCriticalSection,
+};
+#[cfg(all(feature = "CriticalCondvar", windows, feature = "windows-sys"))]
+use windows_sys::Win32::System::Threading::{
+    SleepConditionVariableCS, WakeAllConditionVariable, WakeConditionVariable,
+};
+#[cfg(all(feature = "CriticalCondvar", feature = "windows-sys"))]
+pub(crate) use windows_sys::Win32::{
+    Foundation::ERROR_TIMEOUT,
+    System::Threading::{CONDITION_VARIABLE, INFINITE},
+};
 
+#[cfg(feature = "windows-sys")]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) type DWORD = u32;
+#[cfg(feature = "windows-sys")]
+#[allow(non_camel_case_types)]
+pub(crate) type LPCRITICAL_SECTION = *mut CRITICAL_SECTION;
+#[cfg(all(feature = "CriticalCondvar", feature = "windows-sys"))]
+#[allow(non_camel_case_types)]
+pub(crate) type PCONDITION_VARIABLE = *mut CONDITION_VARIABLE;
+#[cfg(all(feature = "SlimRwLock", feature = "windows-sys"))]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) type PSRWLOCK = *mut SRWLOCK;
+
+#[cfg(feature = "windows-sys")]
+pub(crate) const CRIT_ZEROED: CRITICAL_SECTION = CRITICAL_SECTION {
+    DebugInfo: core::ptr::null_mut(),
+    LockCount: 0,
+    LockSemaphore: 0,
+    OwningThread: 0,
+    RecursionCount: 0,
+    SpinCount: 0,
+};
+// CRIT_ZEROED and the atomic reads of LockCount, OwningThread and SpinCount rely on the
+// documented RTL_CRITICAL_SECTION layout: DebugInfo, LockCount and RecursionCount as LONGs,
+// then OwningThread, LockSemaphore and SpinCount, each pointer-sized. Either backend
+// drifting from it must not compile.
+const PTR: usize = mem::size_of::<usize>();
+const_assert_eq!(mem::size_of::<CRITICAL_SECTION>(), 4 * PTR + 8);
+const_assert_eq!(
+    mem::align_of::<CRITICAL_SECTION>(),
+    mem::align_of::<usize>()
+);
+const_assert_eq!(mem::offset_of!(CRITICAL_SECTION, LockCount), PTR);
+const_assert_eq!(mem::offset_of!(CRITICAL_SECTION, RecursionCount), PTR + 4);
+const_assert_eq!(mem::offset_of!(CRITICAL_SECTION, OwningThread), PTR + 8);
+const_assert_eq!(mem::offset_of!(CRITICAL_SECTION, SpinCount), 3 * PTR + 8);
+
+#[cfg(all(feature = "CriticalCondvar", feature = "windows-sys"))]
+pub(crate) const CONDITION_VARIABLE_INIT: CONDITION_VARIABLE = CONDITION_VARIABLE {
+    Ptr: core::ptr::null_mut(),
+};
+#[cfg(all(feature = "SlimRwLock", feature = "windows-sys"))]
+pub(crate) const SRWLOCK_INIT: SRWLOCK = SRWLOCK {
+    Ptr: core::ptr::null_mut(),
+};
+
+// RTL_CRITICAL_SECTION_FLAG_* values, which the backends spread across several modules.
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+pub(crate) const CRITICAL_SECTION_NO_DEBUG_INFO: DWORD = 0x0100_0000;
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+pub(crate) const CRITICAL_SECTION_DYNAMIC_SPIN: DWORD = 0x0200_0000;
+
+/// Never fails on Windows Vista and later.
+#[allow(non_snake_case)]
+pub unsafe fn init_cs(lpCriticalSection: LPCRITICAL_SECTION) -> Result<(), DWORD> {
+    InitializeCriticalSection(lpCriticalSection);
+    Ok(())
+}
 #[allow(non_snake_case)]
-pub unsafe fn init_cs(lpCriticalSection: LPCRITICAL_SECTION) {
-    match _c_init_cs(lpCriticalSection) {
-        0 => panic!("Failed to initialize critical section"),
-        _ => return,
+pub unsafe fn init_cs_with_spin_count(
+    lpCriticalSection: LPCRITICAL_SECTION,
+    spin_count: DWORD,
+) -> Result<(), DWORD> {
+    match InitializeCriticalSectionAndSpinCount(lpCriticalSection, spin_count) {
+        0 => Err(GetLastError()),
+        _ => Ok(()),
+    }
+}
+// InitializeCriticalSectionEx is looked up at runtime rather than imported, since Wine and
+// other Windows-compatible runtimes may not have it, and a missing import would stop the
+// process from loading at all.
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+type InitializeCriticalSectionExFn =
+    unsafe extern "system" fn(LPCRITICAL_SECTION, DWORD, DWORD) -> i32;
+// 0 until looked up, then NO_INIT_CS_EX if kernel32 does not have it.
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+static INIT_CS_EX: AtomicUsize = AtomicUsize::new(0);
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+const NO_INIT_CS_EX: usize = 1;
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
+fn init_cs_ex_fn() -> Option<InitializeCriticalSectionExFn> {
+    let mut addr = INIT_CS_EX.load(Ordering::Relaxed);
+    if addr == 0 {
+        // Racing threads find the same address, so any of them may store it.
+        addr = match proc_address(b"kernel32.dll\0", b"InitializeCriticalSectionEx\0") {
+            0 => NO_INIT_CS_EX,
+            addr => addr,
+        };
+        INIT_CS_EX.store(addr, Ordering::Relaxed);
+    }
+    match addr {
+        NO_INIT_CS_EX => None,
+        // Safety: GetProcAddress returned this for InitializeCriticalSectionEx, which has
+        // this signature.
+        addr => Some(unsafe { mem::transmute::<usize, InitializeCriticalSectionExFn>(addr) }),
+    }
+}
+// Both names must be nul-terminated. Returns 0 if either is not found.
+#[cfg(all(
+    any(feature = "CriticalSection", feature = "CriticalStatic"),
+    windows,
+    not(feature = "windows-sys")
+))]
+fn proc_address(module: &[u8], name: &[u8]) -> usize {
+    // Safety: both are nul-terminated, and a null module makes GetProcAddress fail.
+    unsafe {
+        match GetModuleHandleA(module.as_ptr() as _) {
+            module if module.is_null() => 0,
+            module => GetProcAddress(module, name.as_ptr() as _) as usize,
+        }
     }
 }
+#[cfg(all(
+    any(feature = "CriticalSection", feature = "CriticalStatic"),
+    windows,
+    feature = "windows-sys"
+))]
+fn proc_address(module: &[u8], name: &[u8]) -> usize {
+    // Safety: both are nul-terminated, and a null module makes GetProcAddress fail.
+    unsafe {
+        match GetModuleHandleA(module.as_ptr()) {
+            0 => 0,
+            module => GetProcAddress(module, name.as_ptr()).map_or(0, |f| f as usize),
+        }
+    }
+}
+/// Returns whether InitializeCriticalSectionEx is available. The result is cached.
+#[cfg(feature = "CriticalSection")]
+pub fn supports_cs_ex() -> bool {
+    init_cs_ex_fn().is_some()
+}
+/// Falls back to InitializeCriticalSectionAndSpinCount, ignoring flags, if
+/// InitializeCriticalSectionEx is not available.
+#[cfg(any(feature = "CriticalSection", feature = "CriticalStatic"))]
 #[allow(non_snake_case)]
-pub unsafe fn init_cs_with_spin_count(lpCriticalSection: LPCRITICAL_SECTION, spin_count: DWORD) {
-    match _c_init_cs_with_spin_count(lpCriticalSection, spin_count) {
-        0 => panic!("Failed to initialize critical section"),
-        _ => return,
+pub unsafe fn init_cs_ex(
+    lpCriticalSection: LPCRITICAL_SECTION,
+    spin_count: DWORD,
+    flags: DWORD,
+) -> Result<(), DWORD> {
+    match init_cs_ex_fn() {
+        Some(init) => match init(lpCriticalSection, spin_count, flags) {
+            0 => Err(GetLastError()),
+            _ => Ok(()),
+        },
+        None => init_cs_with_spin_count(lpCriticalSection, spin_count),
     }
 }
+/// EnterCriticalSection has no return value and cannot fail for lack of memory since
+/// Windows Vista. EXCEPTION_POSSIBLE_DEADLOCK is a structured exception raised only after a
+/// registry-configured timeout, which Rust cannot catch, so there is no error to report.
 #[allow(non_snake_case)]
 pub unsafe fn enter_cs(lpCriticalSection: LPCRITICAL_SECTION) {
-    match _c_enter_cs(lpCriticalSection) {
-        0 => panic!("Failed to initialize critical section"),
-        _ => return,
-    }
+    EnterCriticalSection(lpCriticalSection);
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        critical = ?lpCriticalSection,
+        owner = ?owning_thread_id(lpCriticalSection),
+        "entered critical section"
+    );
 }
 #[allow(non_snake_case)]
 pub unsafe fn try_enter_cs(lpCriticalSection: LPCRITICAL_SECTION) -> DWORD {
-    _c_try_enter_cs(lpCriticalSection)
+    let result = TryEnterCriticalSection(lpCriticalSection) as DWORD;
+    #[cfg(feature = "tracing")]
+    match result {
+        0 => tracing::debug!(
+            critical = ?lpCriticalSection,
+            owner = ?owning_thread_id(lpCriticalSection),
+            "critical section contended"
+        ),
+        _ => tracing::trace!(
+            critical = ?lpCriticalSection,
+            owner = ?owning_thread_id(lpCriticalSection),
+            "entered critical section"
+        ),
+    }
+    result
 }
 #[allow(non_snake_case)]
 pub unsafe fn leave_cs(lpCriticalSection: LPCRITICAL_SECTION) {
-    _c_leave_cs(lpCriticalSection)
+    #[cfg(feature = "tracing")]
+    tracing::trace!(critical = ?lpCriticalSection, "leaving critical section");
+    LeaveCriticalSection(lpCriticalSection)
+}
+/// OwningThread is a pointer-sized field written by other threads as they enter and leave,
+/// so it is read atomically. The value may be stale once read.
+#[allow(non_snake_case)]
+pub unsafe fn owning_thread_id(lpCriticalSection: LPCRITICAL_SECTION) -> Option<DWORD> {
+    let ptr = addr_of!((*lpCriticalSection).OwningThread) as *const AtomicUsize;
+    match (*ptr).load(Ordering::Relaxed) {
+        0 => None,
+        id => Some(id as DWORD),
+    }
 }
 #[allow(non_snake_case)]
 pub unsafe fn delete_cs(lpCriticalSection: LPCRITICAL_SECTION) {
-    _c_delete_cs(lpCriticalSection)
+    DeleteCriticalSection(lpCriticalSection)
 }
 #[allow(non_snake_case)]
 pub unsafe fn set_cs_spin_count(lpCriticalSection: LPCRITICAL_SECTION, spin_count: DWORD) -> DWORD {
-    _c_set_cs_spin_count(lpCriticalSection, spin_count)
+    SetCriticalSectionSpinCount(lpCriticalSection, spin_count)
+}
+#[cfg(any(
+    feature = "CriticalSection",
+    feature = "spin-fallback",
+    debug_assertions
+))]
+pub fn current_thread_id() -> DWORD {
+    // Safety: cannot fail. Naturally thread-safe.
+    unsafe { GetCurrentThreadId() }
+}
+#[cfg(feature = "CriticalSection")]
+pub fn yield_thread() {
+    // Safety: returns whether another thread was scheduled, which we don't care about.
+    unsafe { SwitchToThread() };
+}
+// 0 until looked up. The processor count does not change while the process runs.
+#[cfg(feature = "CriticalSection")]
+static PROCESSOR_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Returns the number of logical processors. The result is cached.
+#[cfg(feature = "CriticalSection")]
+pub fn processor_count() -> DWORD {
+    let mut count = PROCESSOR_COUNT.load(Ordering::Relaxed);
+    if count == 0 {
+        count = system_processor_count() as usize;
+        PROCESSOR_COUNT.store(count, Ordering::Relaxed);
+    }
+    count as DWORD
+}
+#[cfg(all(feature = "CriticalSection", windows))]
+fn system_processor_count() -> DWORD {
+    // Safety: cannot fail, and fills in the whole SYSTEM_INFO.
+    unsafe {
+        let mut info: SYSTEM_INFO = mem::zeroed();
+        GetSystemInfo(&mut info);
+        info.dwNumberOfProcessors
+    }
+}
+#[cfg(feature = "CriticalCondvar")]
+#[allow(non_snake_case)]
+pub unsafe fn sleep_cv_cs(
+    ConditionVariable: PCONDITION_VARIABLE,
+    lpCriticalSection: LPCRITICAL_SECTION,
+    dwMilliseconds: DWORD,
+) -> Result<(), DWORD> {
+    match SleepConditionVariableCS(ConditionVariable, lpCriticalSection, dwMilliseconds) {
+        0 => Err(GetLastError()),
+        _ => Ok(()),
+    }
+}
+#[cfg(feature = "CriticalCondvar")]
+#[allow(non_snake_case)]
+pub unsafe fn wake_cv(ConditionVariable: PCONDITION_VARIABLE) {
+    WakeConditionVariable(ConditionVariable)
+}
+#[cfg(feature = "CriticalCondvar")]
+#[allow(non_snake_case)]
+pub unsafe fn wake_all_cv(ConditionVariable: PCONDITION_VARIABLE) {
+    WakeAllConditionVariable(ConditionVariable)
+}
+#[cfg(feature = "SlimRwLock")]
+#[allow(non_snake_case)]
+pub unsafe fn acquire_srw_shared(SRWLock: PSRWLOCK) {
+    AcquireSRWLockShared(SRWLock)
+}
+#[cfg(feature = "SlimRwLock")]
+#[allow(non_snake_case)]
+pub unsafe fn acquire_srw_exclusive(SRWLock: PSRWLOCK) {
+    AcquireSRWLockExclusive(SRWLock)
+}
+/// Returns whether the lock was acquired.
+#[cfg(feature = "SlimRwLock")]
+#[allow(non_snake_case)]
+pub unsafe fn try_acquire_srw_shared(SRWLock: PSRWLOCK) -> bool {
+    TryAcquireSRWLockShared(SRWLock) != 0
+}
+/// Returns whether the lock was acquired.
+#[cfg(feature = "SlimRwLock")]
+#[allow(non_snake_case)]
+pub unsafe fn try_acquire_srw_exclusive(SRWLock: PSRWLOCK) -> bool {
+    TryAcquireSRWLockExclusive(SRWLock) != 0
+}
+#[cfg(feature = "SlimRwLock")]
+#[allow(non_snake_case)]
+pub unsafe fn release_srw_shared(SRWLock: PSRWLOCK) {
+    ReleaseSRWLockShared(SRWLock)
+}
+#[cfg(feature = "SlimRwLock")]
+#[allow(non_snake_case)]
+pub unsafe fn release_srw_exclusive(SRWLock: PSRWLOCK) {
+    ReleaseSRWLockExclusive(SRWLock)
+}
+
+// Stand-ins for the Win32 functions and, for winapi, types this module uses. Every function
+// panics, since there is nothing to call. Parameters are generic so the same stubs serve
+// both backends.
+#[cfg(not(windows))]
+#[allow(dead_code, non_snake_case, clippy::upper_case_acronyms)]
+mod stubs {
+    #[cold]
+    fn unsupported() -> ! {
+        panic!("critirs only supports Windows")
+    }
+
+    pub unsafe fn InitializeCriticalSection<T>(_: *mut T) {
+        unsupported()
+    }
+    pub unsafe fn InitializeCriticalSectionAndSpinCount<T>(_: *mut T, _: u32) -> i32 {
+        unsupported()
+    }
+    pub unsafe fn EnterCriticalSection<T>(_: *mut T) {
+        unsupported()
+    }
+    pub unsafe fn TryEnterCriticalSection<T>(_: *mut T) -> i32 {
+        unsupported()
+    }
+    pub unsafe fn LeaveCriticalSection<T>(_: *mut T) {
+        unsupported()
+    }
+    pub unsafe fn DeleteCriticalSection<T>(_: *mut T) {
+        unsupported()
+    }
+    pub unsafe fn SetCriticalSectionSpinCount<T>(_: *mut T, _: u32) -> u32 {
+        unsupported()
+    }
+    pub fn proc_address(_: &[u8], _: &[u8]) -> usize {
+        unsupported()
+    }
+    pub fn system_processor_count() -> u32 {
+        unsupported()
+    }
+    pub unsafe fn GetLastError() -> u32 {
+        unsupported()
+    }
+    pub unsafe fn GetCurrentThreadId() -> u32 {
+        unsupported()
+    }
+    pub unsafe fn SwitchToThread() -> i32 {
+        unsupported()
+    }
+    pub unsafe fn SleepConditionVariableCS<C, T>(_: *mut C, _: *mut T, _: u32) -> i32 {
+        unsupported()
+    }
+    pub unsafe fn WakeConditionVariable<C>(_: *mut C) {
+        unsupported()
+    }
+    pub unsafe fn WakeAllConditionVariable<C>(_: *mut C) {
+        unsupported()
+    }
+    pub unsafe fn AcquireSRWLockShared<L>(_: *mut L) {
+        unsupported()
+    }
+    pub unsafe fn AcquireSRWLockExclusive<L>(_: *mut L) {
+        unsupported()
+    }
+    pub unsafe fn TryAcquireSRWLockShared<L>(_: *mut L) -> u8 {
+        unsupported()
+    }
+    pub unsafe fn TryAcquireSRWLockExclusive<L>(_: *mut L) -> u8 {
+        unsupported()
+    }
+    pub unsafe fn ReleaseSRWLockShared<L>(_: *mut L) {
+        unsupported()
+    }
+    pub unsafe fn ReleaseSRWLockExclusive<L>(_: *mut L) {
+        unsupported()
+    }
+
+    // Laid out as winapi defines them, so the layout assertions above still hold.
+    #[allow(non_camel_case_types)]
+    pub mod types {
+        use core::ffi::c_void;
+
+        pub type DWORD = u32;
+        pub const ERROR_BUSY: DWORD = 170;
+        pub const ERROR_ALREADY_INITIALIZED: DWORD = 1247;
+        pub const ERROR_TIMEOUT: DWORD = 1460;
+        pub const INFINITE: DWORD = 0xFFFF_FFFF;
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct CRITICAL_SECTION {
+            pub DebugInfo: *mut c_void,
+            pub LockCount: i32,
+            pub RecursionCount: i32,
+            pub OwningThread: *mut c_void,
+            pub LockSemaphore: *mut c_void,
+            pub SpinCount: usize,
+        }
+        pub type LPCRITICAL_SECTION = *mut CRITICAL_SECTION;
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct CONDITION_VARIABLE {
+            pub Ptr: *mut c_void,
+        }
+        pub type PCONDITION_VARIABLE = *mut CONDITION_VARIABLE;
+        pub const CONDITION_VARIABLE_INIT: CONDITION_VARIABLE = CONDITION_VARIABLE {
+            Ptr: core::ptr::null_mut(),
+        };
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct SRWLOCK {
+            pub Ptr: *mut c_void,
+        }
+        pub type PSRWLOCK = *mut SRWLOCK;
+        pub const SRWLOCK_INIT: SRWLOCK = SRWLOCK {
+            Ptr: core::ptr::null_mut(),
+        };
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "CriticalSection")]
+mod tests {
+    use super::{
+        current_thread_id, delete_cs, enter_cs, init_cs, leave_cs, owning_thread_id, CRIT_ZEROED,
+    };
+
+    #[test]
+    fn init_zeroed_round_trip() {
+        let mut critical = Box::new(CRIT_ZEROED);
+        let ptr = &mut *critical as *mut _;
+        unsafe {
+            init_cs(ptr).unwrap();
+            // Since Windows Vista, LockCount is -1 while free
+            assert_eq!(-1, (*ptr).LockCount);
+            assert_eq!(None, owning_thread_id(ptr));
+            enter_cs(ptr);
+            assert_eq!(Some(current_thread_id()), owning_thread_id(ptr));
+            assert_eq!(1, (*ptr).RecursionCount);
+            leave_cs(ptr);
+            assert_eq!(-1, (*ptr).LockCount);
+            assert_eq!(0, (*ptr).RecursionCount);
+            assert_eq!(None, owning_thread_id(ptr));
+            delete_cs(ptr);
+        }
+    }
 }